@@ -1,10 +1,12 @@
 #![allow(unused)] // FIXME: remove this when functionality is implemented
 
 use std::{
+    fmt::{self, Display},
     net::Ipv4Addr,
     path::{Path, PathBuf},
 };
 
+use semver::{Version, VersionReq};
 use serde::Deserialize;
 
 #[derive(Deserialize)]
@@ -27,13 +29,174 @@ pub struct ServerConfig {
     pub ip: Ipv4Addr,
     pub port: u16,
     pub db: String,
+    /// The URL under which the registry is reachable by clients (e.g. `https://crates.example.com`).
+    ///
+    /// Defaults to `http://{ip}:{port}` if not set.
+    pub public_url: Option<String>,
+}
+
+impl ServerConfig {
+    /// Returns the public base URL of the registry without a trailing slash.
+    pub fn public_url(&self) -> String {
+        match &self.public_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => format!("http://{}:{}", self.ip, self.port),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub struct IndexConfig {
     pub path: PathBuf,
     pub git: bool,
     pub sparse: bool,
+    /// Other registries the crates in this index may depend on.
+    #[serde(default = "default_allowed_registries")]
+    pub allowed_registries: Vec<String>,
+    /// Whether cargo has to authenticate for every request to the registry.
+    #[serde(default)]
+    pub auth_required: bool,
+    /// Whether crates not published locally are fetched from the upstream registry and cached.
+    #[serde(default)]
+    pub crates_io_proxy: bool,
+    /// The sparse index of the registry proxied in crates.io proxy mode.
+    #[serde(default = "default_proxy_upstream")]
+    pub proxy_upstream: String,
+    /// How long connecting to the upstream registry may take, in seconds.
+    #[serde(default = "default_proxy_connect_timeout")]
+    pub proxy_connect_timeout: u64,
+    /// How long a request to the upstream registry may take in total, in seconds.
+    #[serde(default = "default_proxy_timeout")]
+    pub proxy_timeout: u64,
+    /// How long cached index files are served before they are refreshed from upstream, in seconds.
+    #[serde(default = "default_proxy_refresh_interval")]
+    pub proxy_refresh_interval: u64,
+    /// The identity commits to the git index are authored with.
+    #[serde(default)]
+    pub git_author: GitAuthorConfig,
+    /// If enabled, only the listed crate versions are allowed.
+    #[serde(default)]
+    pub whitelist: CrateListConfig,
+    /// If enabled, the listed crate versions are rejected.
+    #[serde(default)]
+    pub blacklist: CrateListConfig,
+    /// The names new crates may not be published under.
+    #[serde(default)]
+    pub name_policy: NamePolicyConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CrateListConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub crates: Vec<CrateRule>,
+}
+
+/// Protects against crates published under confusable or reserved names.
+///
+/// Names are compared the way cargo does: case-insensitive, with `-` and `_` being equal.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct NamePolicyConfig {
+    /// New crates are rejected if their name is within this edit distance of an
+    /// existing local crate or a protected name. `0` only rejects exact matches.
+    #[serde(default)]
+    pub max_distance: usize,
+    /// Names of well-known crates (e.g. from crates.io) that may not be imitated.
+    #[serde(default)]
+    pub protected: Vec<String>,
+    /// Names no crate may be published under.
+    #[serde(default)]
+    pub reserved: Vec<String>,
+    /// Crates the policy does not apply to.
+    #[serde(default)]
+    pub allowed: Vec<String>,
+}
+
+/// A crate name with the versions a white- or blacklist entry applies to.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct CrateRule {
+    pub name: String,
+    /// Either a single requirement (`">1.2, <=2.0"`) or a list of
+    /// requirements of which any has to match (`["1.2", ">2.0"]`).
+    /// All versions are matched if not set.
+    #[serde(default)]
+    pub version: Option<VersionReqs>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum VersionReqs {
+    One(VersionReq),
+    Any(Vec<VersionReq>),
+}
+
+impl CrateRule {
+    /// Whether the rule applies to the given crate version.
+    ///
+    /// Names are compared the way cargo does: case-insensitive, with `-` and `_` being equal.
+    pub fn matches(&self, name: &str, version: &Version) -> bool {
+        let canonical = |name: &str| name.to_lowercase().replace('_', "-");
+        if canonical(&self.name) != canonical(name) {
+            return false;
+        }
+
+        match &self.version {
+            None => true,
+            Some(VersionReqs::One(req)) => req.matches(version),
+            Some(VersionReqs::Any(reqs)) => reqs.iter().any(|req| req.matches(version)),
+        }
+    }
+}
+
+impl Display for CrateRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.version {
+            None => write!(f, "{} (all versions)", self.name),
+            Some(VersionReqs::One(req)) => write!(f, "{} ({})", self.name, req),
+            Some(VersionReqs::Any(reqs)) => {
+                let reqs = reqs.iter().map(ToString::to_string).collect::<Vec<_>>();
+                write!(f, "{} ({})", self.name, reqs.join(" | "))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GitAuthorConfig {
+    pub name: String,
+    pub email: String,
+}
+
+impl Default for GitAuthorConfig {
+    fn default() -> Self {
+        Self {
+            name: "Valhalla".into(),
+            email: "valhalla@localhost".into(),
+        }
+    }
+}
+
+fn default_proxy_upstream() -> String {
+    "sparse+https://index.crates.io/".into()
+}
+
+fn default_proxy_connect_timeout() -> u64 {
+    5
+}
+
+fn default_proxy_timeout() -> u64 {
+    30
+}
+
+fn default_proxy_refresh_interval() -> u64 {
+    5 * 60
+}
+
+fn default_allowed_registries() -> Vec<String> {
+    vec!["https://github.com/rust-lang/crates.io-index".into()]
 }
 
 #[derive(Debug, Deserialize)]
@@ -41,6 +204,20 @@ pub struct StorageConfig {
     #[serde(rename = "type")]
     pub kind: String,
     pub path: PathBuf,
+    /// The maximum size of an uploaded `.crate` file in bytes.
+    #[serde(default = "default_max_crate_size")]
+    pub max_crate_size: u64,
+    /// The maximum size of the unpacked contents of a `.crate` file in bytes.
+    #[serde(default = "default_max_unpacked_size")]
+    pub max_unpacked_size: u64,
+}
+
+fn default_max_crate_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_max_unpacked_size() -> u64 {
+    512 * 1024 * 1024
 }
 
 #[derive(Debug, Deserialize)]
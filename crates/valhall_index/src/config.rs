@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+/// The `config.json` document at the root of the index.
+///
+/// It tells cargo where to download crates from and where the web API lives.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct RegistryConfig {
+    /// The download URL template (e.g. `https://example.com/api/v1/crates/{crate}/{version}/download`).
+    pub dl: String,
+    /// The base URL of the web API.
+    pub api: String,
+    /// The registries crates in this index are allowed to depend on.
+    #[serde(default)]
+    pub allowed_registries: Vec<String>,
+    /// Whether cargo has to send a token with every request.
+    #[serde(default)]
+    pub auth_required: bool,
+}
+
+impl RegistryConfig {
+    /// Creates the config for a registry reachable at `base_url`.
+    ///
+    /// Both index flavours served by the registry itself are always allowed.
+    pub fn new(base_url: &str, mut allowed_registries: Vec<String>, auth_required: bool) -> Self {
+        let base_url = base_url.trim_end_matches('/');
+        allowed_registries.push(format!("sparse+{}/api/v1/index/", base_url));
+        allowed_registries.push(format!("{}/git/index", base_url));
+
+        Self {
+            dl: format!("{}/api/v1/crates/{{crate}}/{{version}}/download", base_url),
+            api: base_url.to_string(),
+            allowed_registries,
+            auth_required,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RegistryConfig;
+
+    #[test]
+    fn config_json() {
+        let config = RegistryConfig::new(
+            "https://crates.example.com/",
+            vec!["https://github.com/rust-lang/crates.io-index".into()],
+            true,
+        );
+
+        assert_eq!(
+            serde_json::to_value(&config).unwrap(),
+            serde_json::json!({
+                "dl": "https://crates.example.com/api/v1/crates/{crate}/{version}/download",
+                "api": "https://crates.example.com",
+                "allowed-registries": [
+                    "https://github.com/rust-lang/crates.io-index",
+                    "sparse+https://crates.example.com/api/v1/index/",
+                    "https://crates.example.com/git/index"
+                ],
+                "auth-required": true
+            })
+        );
+    }
+}
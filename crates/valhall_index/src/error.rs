@@ -11,6 +11,9 @@ pub enum Error {
     /// JSON (de)serialization error (invalid JSON parsed, etc...).
     #[error("JSON error: {0}")]
     JSONError(#[from] serde_json::Error),
+    /// Git error (repository not found, corrupted objects, etc...).
+    #[error("git error: {0}")]
+    GitError(#[from] git2::Error),
     /// Other index-specific error (crate not found, etc...).
     #[error("index-specific error: {0}")]
     IndexError(#[from] IndexError),
@@ -25,4 +28,18 @@ pub enum IndexError {
         /// The requested crate's name.
         name: String,
     },
+    /// A record does not belong to the crate whose index file it is written to.
+    #[error("record of crate '{found}' does not belong to crate '{expected}'")]
+    RecordNameMismatch {
+        /// The name of the crate whose index file is written.
+        expected: String,
+        /// The name found in the record.
+        found: String,
+    },
+    /// A git client sent a request that violates the smart-HTTP protocol.
+    #[error("invalid git protocol request: {msg}")]
+    GitProtocol {
+        /// What is wrong with the request.
+        msg: String,
+    },
 }
@@ -1,12 +1,12 @@
+use git2::Oid;
 use semver::{Version, VersionReq};
-use std::{
-    path::PathBuf,
-    process::{Command, Stdio},
-    sync::Mutex,
-};
+use std::{path::PathBuf, sync::Mutex};
 
-use super::{error::Error, IndexTrait};
-use crate::tree::Tree;
+use super::{config::RegistryConfig, error::Error, IndexTrait};
+use crate::{
+    tree::{self, Tree, TreeLock},
+    upload_pack,
+};
 use valhall_models::crates::CrateVersion;
 
 #[derive(Debug)]
@@ -17,17 +17,199 @@ pub struct GitIndex {
 }
 
 impl GitIndex {
-    pub fn new(repo_path: PathBuf) -> Self {
-        Self {
+    /// Opens the index repository at `repo_path`, initializing it if it does not exist yet.
+    pub fn new(repo_path: PathBuf, author: CommitAuthor) -> Result<Self, Error> {
+        let repo = Repository {
+            path: repo_path.clone(),
+            author,
+        };
+        repo.init()?;
+
+        Ok(Self {
             lock: Mutex::new(()),
-            repo: Repository {
-                path: repo_path.clone(),
-            },
+            repo,
             tree: Tree::new(repo_path),
+        })
+    }
+
+    /// Returns the file tree holding the index records.
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+
+    /// Writes the `config.json` into the repository and commits it if it changed.
+    pub fn write_config(&self, config: &RegistryConfig) -> Result<(), Error> {
+        let _lock = self.lock.lock();
+        let tree = self.tree.lock()?;
+        if tree.write_config(config)? {
+            self.repo.commit("update config.json")?;
         }
+        Ok(())
+    }
+
+    /// Caches the index file of a crate fetched from an upstream registry and commits it if it changed.
+    pub fn cache_record_file(&self, name: &str, contents: &[u8]) -> Result<(), Error> {
+        let _lock = self.lock.lock();
+        let tree = self.tree.lock()?;
+        if tree.write_record_file(name, contents)? {
+            self.repo.commit(&format!("cache {} from upstream", name))?;
+        }
+        Ok(())
+    }
+
+    /// Replaces all crate records of the index with `records`.
+    ///
+    /// The new records are only committed if `commit` is set,
+    /// otherwise they are left in the working tree for review.
+    pub fn rebuild(&self, records: Vec<CrateVersion>, commit: bool) -> Result<(), Error> {
+        let _lock = self.lock.lock();
+        let tree = self.tree.lock()?;
+        tree.replace_records(records)?;
+        if commit {
+            self.repo.commit("rebuild index from stored crates")?;
+        }
+        Ok(())
+    }
+
+    /// Moves index files at mixed-case paths to their lowercase paths and commits the changes.
+    ///
+    /// Returns the names of the moved crates.
+    pub fn normalize_record_paths(&self) -> Result<Vec<String>, Error> {
+        let _lock = self.lock.lock();
+        let tree = self.tree.lock()?;
+        let moved = tree.normalize_record_paths()?;
+        if !moved.is_empty() {
+            self.repo.commit("move index files to lowercase paths")?;
+        }
+        Ok(moved)
+    }
+
+    /// Builds the ref advertisement for git clients fetching the index over smart HTTP.
+    ///
+    /// With a `filter`, the filtered history of [`FILTERED_REF`] is advertised instead of the branch.
+    pub fn advertise_refs(&self, filter: Option<RecordFilter>) -> Result<Vec<u8>, Error> {
+        let repo = git2::Repository::open(&self.repo.path)?;
+        let head = self.served_head(&repo, filter)?;
+        Ok(upload_pack::advertise_refs(
+            head.as_ref().map(|(name, oid)| (name.as_str(), *oid)),
+        ))
+    }
+
+    /// Answers a `git-upload-pack` request of a git client fetching the index over smart HTTP.
+    ///
+    /// `filter` has to be the same as for the advertisement the client received.
+    pub fn upload_pack(&self, body: &[u8], filter: Option<RecordFilter>) -> Result<Vec<u8>, Error> {
+        let repo = git2::Repository::open(&self.repo.path)?;
+        let head = self.served_head(&repo, filter)?;
+        upload_pack::upload_pack(&repo, body, head.map(|(_, oid)| oid))
+    }
+
+    /// Returns the branch `HEAD` points to and the commit served to git clients for it.
+    fn served_head(
+        &self,
+        repo: &git2::Repository,
+        filter: Option<RecordFilter>,
+    ) -> Result<Option<(String, Oid)>, Error> {
+        let head = match repo.head() {
+            Ok(head) => head,
+            Err(err) if err.code() == git2::ErrorCode::UnbornBranch => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let name = head.name().unwrap_or("refs/heads/main").to_string();
+        let commit = head.peel_to_commit()?;
+        let oid = match filter {
+            Some(filter) => {
+                let _lock = self.lock.lock();
+                filtered_commit(repo, &commit, filter)?
+            }
+            None => commit.id(),
+        };
+        Ok(Some((name, oid)))
     }
 }
 
+/// Removes the records that may not be served from the contents of an index file.
+pub type RecordFilter<'a> = &'a dyn Fn(&[u8]) -> Vec<u8>;
+
+/// The ref of the filtered history served to git clients if records are filtered.
+///
+/// Every commit of it mirrors a commit of the branch with the filtered index files,
+/// so the branch and the working tree keep all records.
+pub const FILTERED_REF: &str = "refs/valhall/filtered";
+
+/// Returns the commit of [`FILTERED_REF`] mirroring `source`, creating it if needed.
+fn filtered_commit(
+    repo: &git2::Repository,
+    source: &git2::Commit,
+    filter: RecordFilter,
+) -> Result<Oid, Error> {
+    let message = format!("filtered index at {}", source.id());
+    let previous = match repo.find_reference(FILTERED_REF) {
+        Ok(reference) => Some(reference.peel_to_commit()?),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+    if let Some(previous) = previous
+        .as_ref()
+        .filter(|previous| previous.message() == Some(message.as_str()))
+    {
+        return Ok(previous.id());
+    }
+
+    let tree = repo.find_tree(filter_tree(repo, &source.tree()?, "", filter)?)?;
+    let oid = repo.commit(
+        Some(FILTERED_REF),
+        &source.author(),
+        &source.committer(),
+        &message,
+        &tree,
+        previous.iter().collect::<Vec<_>>().as_slice(),
+    )?;
+    Ok(oid)
+}
+
+/// Writes a copy of `tree` whose index files only hold the records `filter` keeps.
+///
+/// Index files and directories left empty are dropped. `prefix` is the path of `tree`.
+fn filter_tree(
+    repo: &git2::Repository,
+    tree: &git2::Tree,
+    prefix: &str,
+    filter: RecordFilter,
+) -> Result<Oid, Error> {
+    let mut builder = repo.treebuilder(None)?;
+    for entry in tree.iter() {
+        let Some(name) = entry.name() else {
+            continue;
+        };
+        let path = format!("{}{}", prefix, name);
+        let oid = match entry.kind() {
+            Some(git2::ObjectType::Tree) => {
+                let subtree = repo.find_tree(entry.id())?;
+                let oid = filter_tree(repo, &subtree, &format!("{}/", path), filter)?;
+                if repo.find_tree(oid)?.is_empty() {
+                    continue;
+                }
+                oid
+            }
+            Some(git2::ObjectType::Blob) if Tree::crate_name_from_path(&path).is_some() => {
+                let blob = repo.find_blob(entry.id())?;
+                let filtered = filter(blob.content());
+                if filtered.is_empty() {
+                    continue;
+                }
+                match filtered == blob.content() {
+                    true => entry.id(),
+                    false => repo.blob(&filtered)?,
+                }
+            }
+            _ => entry.id(),
+        };
+        builder.insert(name, oid, entry.filemode())?;
+    }
+    Ok(builder.write()?)
+}
+
 impl IndexTrait for GitIndex {
     fn add_record(&self, record: CrateVersion) -> Result<(), Error> {
         tracing::trace!(
@@ -36,15 +218,17 @@ impl IndexTrait for GitIndex {
             record.version
         );
         let _lock = self.lock.lock();
-        // step 0: aquire the lock to block other threads
-        //         to commit / push at the same time
+        // step 0: aquire the locks to block other threads and processes
+        //         from changing or committing the tree at the same time
+        let tree = self.tree.lock()?;
         // step 1: create file
-        // step 2: commit and push change
+        // step 2: commit change
         let msg = format!("added crate {} ({})", record.name, record.version);
         tracing::debug!(index_msg =? msg);
-        self.tree.add_record(record)?;
-        self.repo.commit_and_push(&msg)?;
-        Ok(())
+        let name = record.name.clone();
+        let previous = self.previous_record_file(&name);
+        tree.add_record(record)?;
+        self.commit_or_reset(&tree, &name, previous, &msg)
     }
 
     fn all_records(&self, name: &str) -> Result<Vec<CrateVersion>, Error> {
@@ -63,71 +247,399 @@ impl IndexTrait for GitIndex {
     where
         F: FnOnce(&mut CrateVersion),
     {
-        self.tree.alter_record(name, version, func)
+        let msg = format!("update {} {}", name, version);
+        self.alter_and_commit(name, version, func, &msg)
+    }
+
+    fn yank_record(&self, name: &str, version: Version) -> Result<(), Error> {
+        let msg = format!("yank {} {}", name, version);
+        self.alter_and_commit(name, version, |krate| krate.yanked = true, &msg)
+    }
+
+    fn unyank_record(&self, name: &str, version: Version) -> Result<(), Error> {
+        let msg = format!("unyank {} {}", name, version);
+        self.alter_and_commit(name, version, |krate| krate.yanked = false, &msg)
     }
 }
 
+impl GitIndex {
+    /// Alters a crate version record and commits the change with `msg`.
+    fn alter_and_commit<F>(
+        &self,
+        name: &str,
+        version: Version,
+        func: F,
+        msg: &str,
+    ) -> Result<(), Error>
+    where
+        F: FnOnce(&mut CrateVersion),
+    {
+        let _lock = self.lock.lock();
+        let tree = self.tree.lock()?;
+        tracing::debug!(index_msg =? msg);
+        let previous = self.previous_record_file(name);
+        tree.alter_record(name, version, func)?;
+        self.commit_or_reset(&tree, name, previous, msg)
+    }
+
+    /// Reads the index file of a crate before it is changed, `None` if it does not exist.
+    fn previous_record_file(&self, name: &str) -> Option<Vec<u8>> {
+        self.tree
+            .read_record_file(name)
+            .ok()
+            .map(|file| file.contents)
+    }
+
+    /// Commits the change to the index file of a crate, resetting the file to `previous` on failure.
+    ///
+    /// Otherwise the uncommitted change would be picked up by the next commit.
+    /// The reset happens under `tree`, the lock the change was made under.
+    fn commit_or_reset(
+        &self,
+        tree: &TreeLock,
+        name: &str,
+        previous: Option<Vec<u8>>,
+        msg: &str,
+    ) -> Result<(), Error> {
+        if let Err(err) = self.repo.commit(msg) {
+            if let Err(reset_err) = tree.reset_record_file(name, previous.as_deref()) {
+                tracing::error!(
+                    "failed to reset the index file of crate '{}': {}",
+                    name,
+                    reset_err
+                );
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+/// The identity the registry authors index commits with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitAuthor {
+    pub name: String,
+    pub email: String,
+}
+
 #[derive(Debug)]
 pub struct Repository {
     pub path: PathBuf,
+    pub author: CommitAuthor,
 }
 
-#[allow(unused)]
 impl Repository {
-    fn url(&self) -> Result<String, Error> {
-        let output = Command::new("git")
-            .arg("remote")
-            .arg("get-url")
-            .arg("origin")
-            .stdout(Stdio::null())
-            .stderr(Stdio::piped())
-            .current_dir(self.path.canonicalize()?)
-            .output()?;
-
-        Ok(String::from_utf8_lossy(output.stdout.as_slice()).into())
-    }
-
-    fn refresh(&self) -> Result<(), Error> {
-        Command::new("git")
-            .arg("pull")
-            .arg("--ff-only")
-            .stdout(Stdio::null())
-            .stderr(Stdio::piped())
-            .current_dir(self.path.canonicalize()?)
-            .spawn()?
-            .wait()?;
+    /// Initializes an empty repository with a `main` branch if none exists yet.
+    fn init(&self) -> Result<(), Error> {
+        match git2::Repository::open(&self.path) {
+            Ok(_) => Ok(()),
+            Err(err) if err.code() == git2::ErrorCode::NotFound => {
+                git2::Repository::init_opts(
+                    &self.path,
+                    git2::RepositoryInitOptions::new()
+                        .initial_head("main")
+                        .mkpath(true),
+                )?;
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Commits all changes of the working tree.
+    ///
+    /// The registry serves the repository itself, so nothing is pushed.
+    /// Nothing is committed if the working tree is unchanged.
+    fn commit(&self, msg: &str) -> Result<(), Error> {
+        let repo = git2::Repository::open(&self.path)?;
+        // the lock and temporary files of the tree never belong into the index
+        repo.add_ignore_rule(&format!("/{}\n.*{}", tree::LOCK_FILE, tree::TEMP_SUFFIX))?;
+
+        // stage all new, modified and deleted files
+        let mut index = repo.index()?;
+        index.add_all(["*"], git2::IndexAddOption::DEFAULT, None)?;
+        index.update_all(["*"], None)?;
+        index.write()?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+
+        let parent = match repo.head() {
+            Ok(head) => Some(head.peel_to_commit()?),
+            Err(err) if err.code() == git2::ErrorCode::UnbornBranch => None,
+            Err(err) => return Err(err.into()),
+        };
+        if parent
+            .as_ref()
+            .is_some_and(|parent| parent.tree_id() == tree.id())
+        {
+            tracing::debug!("nothing to commit for '{}'", msg);
+            return Ok(());
+        }
+
+        let signature = git2::Signature::now(&self.author.name, &self.author.email)?;
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            msg,
+            &tree,
+            parent.iter().collect::<Vec<_>>().as_slice(),
+        )?;
 
         Ok(())
     }
+}
 
-    fn commit_and_push(&self, msg: &str) -> Result<(), Error> {
-        Command::new("git")
-            .arg("add")
-            .arg("--all")
-            .stdout(Stdio::null())
-            .stderr(Stdio::piped())
-            .current_dir(&self.path)
-            .spawn()?
-            .wait()?;
-        Command::new("git")
-            .arg("commit")
-            .arg("-m")
-            .arg(msg)
-            .stdout(Stdio::null())
-            .stderr(Stdio::piped())
-            .current_dir(&self.path)
-            .spawn()?
-            .wait()?;
-        Command::new("git")
-            .arg("push")
-            .arg("origin")
-            .arg("main")
-            .stdout(Stdio::null())
-            .stderr(Stdio::piped())
-            .current_dir(&self.path)
-            .spawn()?
-            .wait()?;
+#[cfg(test)]
+mod tests {
+    use super::{CommitAuthor, GitIndex, FILTERED_REF};
+    use crate::{Index, IndexTrait};
+    use semver::Version;
+    use valhall_models::crates::CrateVersion;
 
-        Ok(())
+    fn record(name: &str, version: &str) -> CrateVersion {
+        CrateVersion {
+            name: name.into(),
+            version: Version::parse(version).unwrap(),
+            dependencies: vec![],
+            checksum: "0".repeat(64),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+            rust_version: None,
+            features2: Default::default(),
+            v: None,
+        }
+    }
+
+    fn author() -> CommitAuthor {
+        CommitAuthor {
+            name: "Registry Bot".into(),
+            email: "bot@example.com".into(),
+        }
+    }
+
+    #[test]
+    fn serve_filtered_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = GitIndex::new(dir.path().to_path_buf(), author()).unwrap();
+        index.add_record(record("abcd", "1.0.0")).unwrap();
+        index.add_record(record("abcd", "2.0.0")).unwrap();
+        index.add_record(record("efgh", "1.0.0")).unwrap();
+
+        // keeps the records of version 2.0.0
+        let filter = |contents: &[u8]| -> Vec<u8> {
+            contents
+                .split_inclusive(|byte| *byte == b'\n')
+                .filter(|line| String::from_utf8_lossy(line).contains("2.0.0"))
+                .flatten()
+                .copied()
+                .collect()
+        };
+        let repo = git2::Repository::open(dir.path()).unwrap();
+        let head = repo.head().unwrap().target().unwrap();
+        let refs = index.advertise_refs(Some(&filter)).unwrap();
+        let served = repo.refname_to_id(FILTERED_REF).unwrap();
+        let refs = String::from_utf8(refs).unwrap();
+        assert!(refs.contains(&format!("{} HEAD", served)));
+        assert!(!refs.contains(&head.to_string()));
+
+        let tree = repo.find_commit(served).unwrap().tree().unwrap();
+        let blob = tree.get_path(std::path::Path::new("ab/cd/abcd")).unwrap();
+        let contents = repo.find_blob(blob.id()).unwrap().content().to_vec();
+        assert_eq!(contents, filter(&contents));
+        assert_eq!(String::from_utf8(contents).unwrap().lines().count(), 1);
+        assert!(tree.get_path(std::path::Path::new("ef/gh")).is_err());
+
+        // the branch and the working tree keep all records
+        assert_eq!(repo.head().unwrap().target(), Some(head));
+        assert_eq!(index.all_records("abcd").unwrap().len(), 2);
+        assert!(index.all_records("efgh").is_ok());
+        assert_committed(dir.path());
+
+        // the filtered commit is reused until the branch changes
+        index.advertise_refs(Some(&filter)).unwrap();
+        assert_eq!(repo.refname_to_id(FILTERED_REF).unwrap(), served);
+        index.add_record(record("efgh", "2.0.0")).unwrap();
+        index.advertise_refs(Some(&filter)).unwrap();
+        let next = repo
+            .find_commit(repo.refname_to_id(FILTERED_REF).unwrap())
+            .unwrap();
+        assert_eq!(next.parent_id(0).unwrap(), served);
+        assert!(next
+            .tree()
+            .unwrap()
+            .get_path(std::path::Path::new("ef/gh/efgh"))
+            .is_ok());
+
+        // without a filter the branch is served
+        let refs = String::from_utf8(index.advertise_refs(None).unwrap()).unwrap();
+        let head = repo.head().unwrap().target().unwrap();
+        assert!(refs.contains(&format!("{} HEAD", head)));
+    }
+
+    #[test]
+    fn add_record_commits() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = GitIndex::new(dir.path().to_path_buf(), author()).unwrap();
+        index.add_record(record("abcd", "1.0.0")).unwrap();
+
+        let repo = git2::Repository::open(dir.path()).unwrap();
+        let head = repo.head().unwrap();
+        assert_eq!(head.name(), Some("refs/heads/main"));
+
+        let commit = head.peel_to_commit().unwrap();
+        assert_eq!(commit.message(), Some("added crate abcd (1.0.0)"));
+        assert_eq!(commit.author().name(), Some("Registry Bot"));
+        assert_eq!(commit.author().email(), Some("bot@example.com"));
+        assert!(commit
+            .tree()
+            .unwrap()
+            .get_path(std::path::Path::new("ab/cd/abcd"))
+            .is_ok());
+        assert_committed(dir.path());
+    }
+
+    #[test]
+    fn add_record_waits_for_tree_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = GitIndex::new(dir.path().to_path_buf(), author()).unwrap();
+        index.add_record(record("abcd", "1.0.0")).unwrap();
+
+        // another process holding the lock keeps the record from being written and committed
+        let other = crate::tree::Tree::new(dir.path().to_path_buf());
+        let lock = other.lock().unwrap();
+        std::thread::scope(|scope| {
+            let handle = scope.spawn(|| index.add_record(record("abcd", "1.1.0")));
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            assert!(!handle.is_finished());
+            assert_eq!(other.all_records("abcd").unwrap().len(), 1);
+
+            drop(lock);
+            handle.join().unwrap().unwrap();
+        });
+        assert_eq!(history(dir.path())[0], "added crate abcd (1.1.0)");
+        assert_committed(dir.path());
+    }
+
+    #[test]
+    fn reopen_existing_repository() {
+        let dir = tempfile::tempdir().unwrap();
+        GitIndex::new(dir.path().to_path_buf(), author())
+            .unwrap()
+            .add_record(record("a", "1.0.0"))
+            .unwrap();
+
+        let index = GitIndex::new(dir.path().to_path_buf(), author()).unwrap();
+        index.add_record(record("a", "1.1.0")).unwrap();
+
+        let repo = git2::Repository::open(dir.path()).unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.parent_count(), 1);
+        assert_eq!(index.all_records("a").unwrap().len(), 2);
+    }
+
+    /// Asserts that everything but the lock file of the tree is committed.
+    fn assert_committed(dir: &std::path::Path) {
+        let repo = git2::Repository::open(dir).unwrap();
+        let statuses = repo.statuses(None).unwrap();
+        let paths = statuses
+            .iter()
+            .filter_map(|entry| entry.path().map(String::from))
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec![crate::tree::LOCK_FILE]);
+    }
+
+    fn history(dir: &std::path::Path) -> Vec<String> {
+        let repo = git2::Repository::open(dir).unwrap();
+        let mut walk = repo.revwalk().unwrap();
+        walk.push_head().unwrap();
+        walk.map(|oid| {
+            let commit = repo.find_commit(oid.unwrap()).unwrap();
+            commit.message().unwrap().to_string()
+        })
+        .collect()
+    }
+
+    #[test]
+    fn yank_and_unyank_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        // the commit messages must survive the dispatch through `Index`
+        let index = Index::Git(GitIndex::new(dir.path().to_path_buf(), author()).unwrap());
+        index.add_record(record("foo", "1.2.3")).unwrap();
+        index.yank_record("foo", Version::new(1, 2, 3)).unwrap();
+        assert!(index.latest_record("foo").unwrap().yanked);
+        index.unyank_record("foo", Version::new(1, 2, 3)).unwrap();
+        assert!(!index.latest_record("foo").unwrap().yanked);
+        index
+            .alter_record("foo", Version::new(1, 2, 3), |krate| {
+                krate.links = Some("foo".into())
+            })
+            .unwrap();
+
+        assert_eq!(
+            history(dir.path()),
+            vec![
+                "update foo 1.2.3",
+                "unyank foo 1.2.3",
+                "yank foo 1.2.3",
+                "added crate foo (1.2.3)",
+            ]
+        );
+        assert_committed(dir.path());
+    }
+
+    #[test]
+    fn unchanged_record_does_not_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = GitIndex::new(dir.path().to_path_buf(), author()).unwrap();
+        index.add_record(record("foo", "1.2.3")).unwrap();
+        index.unyank_record("foo", Version::new(1, 2, 3)).unwrap();
+        assert!(index.yank_record("foo", Version::new(2, 0, 0)).is_err());
+
+        assert_eq!(history(dir.path()), vec!["added crate foo (1.2.3)"]);
+    }
+
+    #[test]
+    fn rebuild_replaces_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = GitIndex::new(dir.path().to_path_buf(), author()).unwrap();
+        index.add_record(record("abcd", "1.0.0")).unwrap();
+        index.add_record(record("xyz", "0.1.0")).unwrap();
+
+        let records = vec![record("abcd", "1.1.0"), record("abcd", "1.0.0")];
+        index.rebuild(records.clone(), false).unwrap();
+        assert_eq!(history(dir.path()).len(), 2);
+        assert!(index.all_records("xyz").is_err());
+
+        index.rebuild(records, true).unwrap();
+        assert_eq!(history(dir.path())[0], "rebuild index from stored crates");
+        assert_committed(dir.path());
+        let versions = index
+            .all_records("abcd")
+            .unwrap()
+            .into_iter()
+            .map(|krate| krate.version)
+            .collect::<Vec<_>>();
+        assert_eq!(versions, vec![Version::new(1, 0, 0), Version::new(1, 1, 0)]);
+    }
+
+    #[test]
+    fn failed_commit_resets_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = GitIndex::new(dir.path().to_path_buf(), author()).unwrap();
+        index.add_record(record("abcd", "1.0.0")).unwrap();
+        let before = index.tree().read_record_file("abcd").unwrap().contents;
+
+        // a stale lock of the git index makes every commit fail
+        std::fs::write(dir.path().join(".git/index.lock"), "").unwrap();
+        assert!(index.add_record(record("abcd", "1.1.0")).is_err());
+        assert!(index.add_record(record("efgh", "1.0.0")).is_err());
+        assert!(index.yank_record("abcd", Version::new(1, 0, 0)).is_err());
+
+        assert_eq!(
+            index.tree().read_record_file("abcd").unwrap().contents,
+            before
+        );
+        assert!(index.tree().read_record_file("efgh").is_err());
     }
 }
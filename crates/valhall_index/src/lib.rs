@@ -1,14 +1,18 @@
+use config::RegistryConfig;
 use error::Error;
 use git::GitIndex;
 use semver::{Version, VersionReq};
 use sparse::SparseIndex;
+use tree::Tree;
 use valhall_models::crates::CrateVersion;
 
+pub mod config;
 pub mod error;
 pub mod git;
 pub mod models;
 pub mod sparse;
 pub mod tree;
+pub mod upload_pack;
 
 pub trait IndexTrait {
     /// Adds a new crate record into the index.
@@ -43,6 +47,51 @@ where
     Sparse(SparseIndex),
 }
 
+impl Index {
+    /// Returns the file tree holding the index records.
+    pub fn tree(&self) -> &Tree {
+        match self {
+            Self::Git(idx) => idx.tree(),
+            Self::Sparse(idx) => idx.tree(),
+        }
+    }
+
+    /// Writes the `config.json` into the root of the index.
+    pub fn write_config(&self, config: &RegistryConfig) -> Result<(), Error> {
+        match self {
+            Self::Git(idx) => idx.write_config(config),
+            Self::Sparse(idx) => idx.write_config(config),
+        }
+    }
+
+    /// Caches the index file of a crate fetched from an upstream registry.
+    pub fn cache_record_file(&self, name: &str, contents: &[u8]) -> Result<(), Error> {
+        match self {
+            Self::Git(idx) => idx.cache_record_file(name, contents),
+            Self::Sparse(idx) => idx.cache_record_file(name, contents),
+        }
+    }
+
+    /// Moves index files written at mixed-case paths by older versions to their
+    /// lowercase paths, returning the names of the moved crates.
+    pub fn normalize_record_paths(&self) -> Result<Vec<String>, Error> {
+        match self {
+            Self::Git(idx) => idx.normalize_record_paths(),
+            Self::Sparse(idx) => idx.normalize_record_paths(),
+        }
+    }
+
+    /// Replaces all crate records of the index with `records`.
+    ///
+    /// `commit` decides whether the git index commits the rebuilt records.
+    pub fn rebuild(&self, records: Vec<CrateVersion>, commit: bool) -> Result<(), Error> {
+        match self {
+            Self::Git(idx) => idx.rebuild(records, commit),
+            Self::Sparse(idx) => idx.rebuild(records),
+        }
+    }
+}
+
 impl IndexTrait for Index {
     fn add_record(&self, record: CrateVersion) -> Result<(), Error> {
         tracing::debug!("adding record!");
@@ -82,4 +131,18 @@ impl IndexTrait for Index {
             Self::Sparse(idx) => idx.alter_record(name, version, func),
         }
     }
+
+    fn yank_record(&self, name: &str, version: Version) -> Result<(), Error> {
+        match self {
+            Self::Git(idx) => idx.yank_record(name, version),
+            Self::Sparse(idx) => idx.yank_record(name, version),
+        }
+    }
+
+    fn unyank_record(&self, name: &str, version: Version) -> Result<(), Error> {
+        match self {
+            Self::Git(idx) => idx.unyank_record(name, version),
+            Self::Sparse(idx) => idx.unyank_record(name, version),
+        }
+    }
 }
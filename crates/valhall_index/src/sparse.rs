@@ -1,33 +1,148 @@
-#![allow(unused)]
-
-use super::{error::Error, IndexTrait};
 use semver::{Version, VersionReq};
+use std::{path::PathBuf, sync::Mutex};
+
+use super::{config::RegistryConfig, error::Error, IndexTrait};
+use crate::tree::Tree;
 use valhall_models::crates::CrateVersion;
 
+/// A file-backed index that is only served through the sparse protocol.
+///
+/// The records are stored in the same layout as the git index,
+/// but no repository is maintained next to them.
 #[derive(Debug)]
-pub struct SparseIndex {}
+pub struct SparseIndex {
+    lock: Mutex<()>,
+    tree: Tree,
+}
+
+impl SparseIndex {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            lock: Mutex::new(()),
+            tree: Tree::new(path),
+        }
+    }
+
+    /// Returns the file tree holding the index records.
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+
+    /// Writes the `config.json` into the root of the index.
+    pub fn write_config(&self, config: &RegistryConfig) -> Result<(), Error> {
+        let _lock = self.lock.lock();
+        self.tree.write_config(config)?;
+        Ok(())
+    }
+
+    /// Caches the index file of a crate fetched from an upstream registry.
+    pub fn cache_record_file(&self, name: &str, contents: &[u8]) -> Result<(), Error> {
+        let _lock = self.lock.lock();
+        self.tree.write_record_file(name, contents)?;
+        Ok(())
+    }
+
+    /// Moves index files at mixed-case paths to their lowercase paths.
+    pub fn normalize_record_paths(&self) -> Result<Vec<String>, Error> {
+        let _lock = self.lock.lock();
+        self.tree.normalize_record_paths()
+    }
+
+    /// Replaces all crate records of the index with `records`.
+    pub fn rebuild(&self, records: Vec<CrateVersion>) -> Result<(), Error> {
+        let _lock = self.lock.lock();
+        self.tree.replace_records(records)
+    }
+}
 
 impl IndexTrait for SparseIndex {
     fn add_record(&self, record: CrateVersion) -> Result<(), Error> {
-        todo!()
+        tracing::trace!(
+            "adding record for crate {} ({})",
+            record.name,
+            record.version
+        );
+        let _lock = self.lock.lock();
+        self.tree.add_record(record)
     }
 
     fn all_records(&self, name: &str) -> Result<Vec<CrateVersion>, Error> {
-        todo!()
+        self.tree.all_records(name)
     }
 
     fn latest_record(&self, name: &str) -> Result<CrateVersion, Error> {
-        todo!()
+        self.tree.latest_record(name)
     }
 
     fn match_record(&self, name: &str, req: VersionReq) -> Result<CrateVersion, Error> {
-        todo!()
+        self.tree.match_record(name, req)
     }
 
     fn alter_record<F>(&self, name: &str, version: Version, func: F) -> Result<(), Error>
     where
         F: FnOnce(&mut CrateVersion),
     {
-        todo!()
+        let _lock = self.lock.lock();
+        self.tree.alter_record(name, version, func)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SparseIndex;
+    use crate::IndexTrait;
+    use semver::{Version, VersionReq};
+    use valhall_models::crates::CrateVersion;
+
+    fn record(name: &str, version: &str) -> CrateVersion {
+        CrateVersion {
+            name: name.into(),
+            version: Version::parse(version).unwrap(),
+            dependencies: vec![],
+            checksum: "0".repeat(64),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+            rust_version: None,
+            features2: Default::default(),
+            v: None,
+        }
+    }
+
+    #[test]
+    fn add_and_read_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = SparseIndex::new(dir.path().to_path_buf());
+
+        index.add_record(record("abcd", "0.1.0")).unwrap();
+        index.add_record(record("abcd", "1.0.0")).unwrap();
+        index.add_record(record("abcd", "0.2.0")).unwrap();
+
+        assert!(dir.path().join("ab").join("cd").join("abcd").is_file());
+        assert_eq!(index.all_records("abcd").unwrap().len(), 3);
+        assert_eq!(
+            index.latest_record("abcd").unwrap().version,
+            Version::new(1, 0, 0)
+        );
+        let matched = index
+            .match_record("abcd", VersionReq::parse("^0.1").unwrap())
+            .unwrap();
+        assert_eq!(matched.version, Version::new(0, 1, 0));
+    }
+
+    #[test]
+    fn yank_and_unyank_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = SparseIndex::new(dir.path().to_path_buf());
+        index.add_record(record("a", "1.0.0")).unwrap();
+
+        index.yank_record("a", Version::new(1, 0, 0)).unwrap();
+        assert!(index.latest_record("a").unwrap().yanked);
+
+        index.unyank_record("a", Version::new(1, 0, 0)).unwrap();
+        assert!(!index.latest_record("a").unwrap().yanked);
+
+        assert!(index.yank_record("a", Version::new(2, 0, 0)).is_err());
+        assert!(index.yank_record("missing", Version::new(1, 0, 0)).is_err());
     }
 }
@@ -1,13 +1,32 @@
+use std::collections::BTreeMap;
 use std::fs;
 use std::io;
 use std::io::{BufRead, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
+use super::config::RegistryConfig;
 use super::error::IndexError;
 use super::Error;
+use fs4::fs_std::FileExt;
 use semver::{Version, VersionReq};
 use valhall_models::crates::CrateVersion;
 
+/// The file in the root of the tree used to serialize writes across processes.
+pub const LOCK_FILE: &str = ".valhall.lock";
+
+/// The suffix of the temporary files index files are written to before being renamed.
+pub const TEMP_SUFFIX: &str = ".tmp";
+
+/// The raw contents of a crate's index file, as served by the sparse protocol.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordFile {
+    /// The newline-delimited JSON records of all versions.
+    pub contents: Vec<u8>,
+    /// The last modification time of the file.
+    pub modified: SystemTime,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Tree {
     path: PathBuf,
@@ -18,23 +37,98 @@ impl Tree {
         Self { path }
     }
 
+    /// Returns the path of a crate's index file relative to the index root (e.g. `3/a/abc`).
+    ///
+    /// Paths are always lowercase, so every spelling of a name maps to the same file.
+    pub fn record_path(name: &str) -> String {
+        let name = name.to_lowercase();
+        format!("{}/{}", Self::prefix(&name), name)
+    }
+
+    /// Returns the directory of a crate's index file (e.g. `3/a` or `Se/rd`), keeping the case of `name`.
+    ///
+    /// Names are split by characters, so names from requests that are not valid
+    /// crate names (e.g. holding multibyte characters) don't panic.
+    pub fn prefix(name: &str) -> String {
+        let chars = name.chars().collect::<Vec<_>>();
+        let part = |range: std::ops::Range<usize>| chars[range].iter().collect::<String>();
+        match chars.len() {
+            1 => "1".to_string(),
+            2 => "2".to_string(),
+            3 => format!("3/{}", part(0..1)),
+            _ => format!("{}/{}", part(0..2), part(2..4)),
+        }
+    }
+
     fn compute_record_path(&self, name: &str) -> PathBuf {
-        match name.len() {
-            1 => self.path.join("1").join(name),
-            2 => self.path.join("2").join(name),
-            3 => self.path.join("3").join(&name[..1]).join(name),
-            _ => self.path.join(&name[0..2]).join(&name[2..4]).join(name),
+        self.path.join(Self::record_path(name))
+    }
+
+    /// Takes the advisory write lock of the tree, blocking until it is available.
+    ///
+    /// The lock is shared with every process using the same index directory
+    /// and is released when the returned guard is dropped.
+    pub fn lock(&self) -> Result<TreeLock<'_>, Error> {
+        fs::create_dir_all(&self.path)?;
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.path.join(LOCK_FILE))?;
+        file.lock_exclusive()?;
+        Ok(TreeLock {
+            tree: self,
+            _file: file,
+        })
+    }
+
+    /// Writes the `config.json` into the root of the tree.
+    ///
+    /// Returns `false` if the file already had the same content.
+    pub fn write_config(&self, config: &RegistryConfig) -> Result<bool, Error> {
+        self.lock()?.write_config(config)
+    }
+
+    /// Returns the name of the crate whose records live at `path` (relative to the index root),
+    /// or `None` if the path does not follow the index layout (e.g. `3/a/abc` or `ab/cd/abcd`).
+    pub fn crate_name_from_path(path: &str) -> Option<&str> {
+        let segments = path.split('/').collect::<Vec<_>>();
+        let (name, prefix) = segments.split_last()?;
+        let is_valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !is_valid_name {
+            return None;
         }
+
+        let expected = Self::prefix(&name.to_lowercase());
+        (prefix.join("/").to_lowercase() == expected).then_some(*name)
     }
 
-    pub fn match_record(&self, name: &str, req: VersionReq) -> Result<CrateVersion, Error> {
+    /// Reads the raw index file of a crate.
+    pub fn read_record_file(&self, name: &str) -> Result<RecordFile, Error> {
+        let mut file = self.open_record(name)?;
+        let modified = file.metadata()?.modified()?;
+        let mut contents = Vec::new();
+        io::Read::read_to_end(&mut file, &mut contents)?;
+
+        Ok(RecordFile { contents, modified })
+    }
+
+    /// Opens the record file of a crate, mapping a missing file to [`IndexError::CrateNotFound`].
+    fn open_record(&self, name: &str) -> Result<fs::File, Error> {
         let path = self.compute_record_path(name);
-        let file = fs::File::open(path).map_err(|err| match err.kind() {
+        fs::File::open(path).map_err(|err| match err.kind() {
             io::ErrorKind::NotFound => Error::from(IndexError::CrateNotFound {
                 name: String::from(name),
             }),
             _ => Error::from(err),
-        })?;
+        })
+    }
+
+    pub fn match_record(&self, name: &str, req: VersionReq) -> Result<CrateVersion, Error> {
+        let file = self.open_record(name)?;
 
         let found = io::BufReader::new(file)
             .lines()
@@ -48,8 +142,7 @@ impl Tree {
     }
 
     pub fn all_records(&self, name: &str) -> Result<Vec<CrateVersion>, Error> {
-        let path = self.compute_record_path(name);
-        let reader = io::BufReader::new(fs::File::open(path)?);
+        let reader = io::BufReader::new(self.open_record(name)?);
         reader
             .lines()
             .map(|line| Ok(serde_json::from_str::<CrateVersion>(line?.as_str())?))
@@ -64,21 +157,225 @@ impl Tree {
             .expect("at least one version should exist"))
     }
 
+    /// Lists the names of all crates with an index file in the tree, in alphabetical order.
+    ///
+    /// Files outside of the index layout (e.g. the `config.json`) are skipped.
+    pub fn crate_names(&self) -> Result<Vec<String>, Error> {
+        let mut names = self
+            .record_file_paths()?
+            .iter()
+            .filter_map(|path| Self::crate_name_from_path(path).map(String::from))
+            .collect::<Vec<_>>();
+        names.sort();
+        Ok(names)
+    }
+
+    /// Lists the paths (relative to the root) of all files following the index layout.
+    fn record_file_paths(&self) -> Result<Vec<String>, Error> {
+        fn walk(dir: &Path, prefix: &str, paths: &mut Vec<String>) -> io::Result<()> {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                if file_name.starts_with('.') {
+                    continue;
+                }
+                let path = format!("{}{}", prefix, file_name);
+                if entry.file_type()?.is_dir() {
+                    walk(&entry.path(), &format!("{}/", path), paths)?;
+                } else if Tree::crate_name_from_path(&path).is_some() {
+                    paths.push(path);
+                }
+            }
+            Ok(())
+        }
+
+        let mut paths = Vec::new();
+        match walk(&self.path, "", &mut paths) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        Ok(paths)
+    }
+
+    /// Moves index files written by older versions at mixed-case paths (e.g. `Se/rd/Serde`)
+    /// to the lowercase path they are looked up at.
+    ///
+    /// If the lowercase file already exists, the records of versions missing from it
+    /// are appended to it. Returns the names of the moved crates.
+    pub fn normalize_record_paths(&self) -> Result<Vec<String>, Error> {
+        self.lock()?.normalize_record_paths()
+    }
+
+    /// Replaces the index file of a crate with records fetched from another registry.
+    ///
+    /// Every line has to be a valid record of the crate. The raw lines are stored as-is,
+    /// so fields unknown to [`CrateVersion`] are preserved.
+    /// Returns `false` if the file already had the same content.
+    pub fn write_record_file(&self, name: &str, contents: &[u8]) -> Result<bool, Error> {
+        self.lock()?.write_record_file(name, contents)
+    }
+
+    /// Resets the index file of a crate to `contents`, removing it if `None`.
+    ///
+    /// Unlike [`Tree::write_record_file`] the contents are not validated,
+    /// as this is meant to undo a change that could not be completed.
+    pub fn reset_record_file(&self, name: &str, contents: Option<&[u8]>) -> Result<(), Error> {
+        self.lock()?.reset_record_file(name, contents)
+    }
+
     pub fn add_record(&self, record: CrateVersion) -> Result<(), Error> {
-        let path = self.compute_record_path(record.name.as_str());
+        self.lock()?.add_record(record)
+    }
+
+    pub fn alter_record<F>(&self, name: &str, version: Version, func: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut CrateVersion),
+    {
+        self.lock()?.alter_record(name, version, func)
+    }
+
+    /// Replaces all index files of the tree with files holding `records`.
+    ///
+    /// The records of each crate are written in ascending version order.
+    /// The `config.json` and hidden files (e.g. the `.git` directory) are kept.
+    pub fn replace_records(&self, records: Vec<CrateVersion>) -> Result<(), Error> {
+        self.lock()?.replace_records(records)
+    }
+}
 
-        if !path.exists() {
-            let parent = path.parent().unwrap();
-            fs::create_dir_all(parent)?;
+/// The advisory write lock of a [`Tree`], see [`Tree::lock`].
+///
+/// Changes made through it happen while the lock is held, so callers can
+/// keep other processes out across several steps (e.g. writing and committing a file).
+#[derive(Debug)]
+pub struct TreeLock<'a> {
+    tree: &'a Tree,
+    _file: fs::File,
+}
+
+impl TreeLock<'_> {
+    /// See [`Tree::write_config`].
+    pub fn write_config(&self, config: &RegistryConfig) -> Result<bool, Error> {
+        let path = self.tree.path.join("config.json");
+        let content = serde_json::to_string_pretty(config)? + "\n";
+        if fs::read_to_string(&path).is_ok_and(|existing| existing == content) {
+            return Ok(false);
         }
 
-        let mut file = fs::OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(path)?;
-        serde_json::to_writer(&mut file, &record)?;
-        writeln!(file)?;
-        file.flush()?;
+        write_atomic(&path, content.as_bytes())?;
+        Ok(true)
+    }
+
+    /// See [`Tree::normalize_record_paths`].
+    pub fn normalize_record_paths(&self) -> Result<Vec<String>, Error> {
+        let mut moved = Vec::new();
+        for path in self.tree.record_file_paths()? {
+            let name = Tree::crate_name_from_path(&path).expect("only record files are listed");
+            let target = Tree::record_path(name);
+            if path == target {
+                continue;
+            }
+
+            // the file is moved aside first, as on case-insensitive file systems
+            // both paths refer to the same file
+            let source = self.tree.path.join(&path);
+            let temp = source.with_file_name(format!(".{}{}", name, TEMP_SUFFIX));
+            fs::rename(&source, &temp)?;
+            let mut contents = fs::read(&temp)?;
+
+            let target = self.tree.path.join(&target);
+            match fs::read(&target) {
+                Ok(existing) => {
+                    let versions = existing
+                        .split(|byte| *byte == b'\n')
+                        .filter_map(|line| serde_json::from_slice::<CrateVersion>(line).ok())
+                        .map(|krate| krate.version)
+                        .collect::<Vec<_>>();
+                    let mut merged = existing.clone();
+                    for line in contents.split(|byte| *byte == b'\n') {
+                        let Ok(krate) = serde_json::from_slice::<CrateVersion>(line) else {
+                            continue;
+                        };
+                        if !versions.contains(&krate.version) {
+                            if !merged.is_empty() && !merged.ends_with(b"\n") {
+                                merged.push(b'\n');
+                            }
+                            merged.extend_from_slice(line);
+                            merged.push(b'\n');
+                        }
+                    }
+                    contents = merged;
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+            write_atomic(&target, &contents)?;
+            fs::remove_file(&temp)?;
+
+            // drop the directories left empty, ignoring those still in use
+            for dir in source.ancestors().skip(1) {
+                if dir == self.tree.path || fs::remove_dir(dir).is_err() {
+                    break;
+                }
+            }
+            moved.push(name.to_string());
+        }
+        moved.sort();
+        Ok(moved)
+    }
+
+    /// See [`Tree::write_record_file`].
+    pub fn write_record_file(&self, name: &str, contents: &[u8]) -> Result<bool, Error> {
+        for line in contents.split(|byte| *byte == b'\n') {
+            if line.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let record = serde_json::from_slice::<CrateVersion>(line)?;
+            if !record.name.eq_ignore_ascii_case(name) {
+                return Err(IndexError::RecordNameMismatch {
+                    expected: String::from(name),
+                    found: record.name,
+                }
+                .into());
+            }
+        }
+
+        let path = self.tree.compute_record_path(name);
+        if fs::read(&path).is_ok_and(|existing| existing == contents) {
+            return Ok(false);
+        }
+
+        write_atomic(&path, contents)?;
+        Ok(true)
+    }
+
+    /// See [`Tree::reset_record_file`].
+    pub fn reset_record_file(&self, name: &str, contents: Option<&[u8]>) -> Result<(), Error> {
+        let path = self.tree.compute_record_path(name);
+        match contents {
+            Some(contents) => write_atomic(&path, contents)?,
+            None => match fs::remove_file(&path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            },
+        }
+        Ok(())
+    }
+
+    pub fn add_record(&self, record: CrateVersion) -> Result<(), Error> {
+        let path = self.tree.compute_record_path(record.name.as_str());
+        // the existing lines are kept byte for byte
+        let mut contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        if !contents.is_empty() && !contents.ends_with(b"\n") {
+            contents.push(b'\n');
+        }
+        serde_json::to_writer(&mut contents, &record)?;
+        writeln!(contents)?;
+        write_atomic(&path, &contents)?;
 
         Ok(())
     }
@@ -87,13 +384,8 @@ impl Tree {
     where
         F: FnOnce(&mut CrateVersion),
     {
-        let path = self.compute_record_path(name);
-        let file = fs::File::open(path.as_path()).map_err(|err| match err.kind() {
-            io::ErrorKind::NotFound => Error::from(IndexError::CrateNotFound {
-                name: String::from(name),
-            }),
-            _ => Error::from(err),
-        })?;
+        let path = self.tree.compute_record_path(name);
+        let file = self.tree.open_record(name)?;
         let mut krates: Vec<CrateVersion> = {
             let mut out = Vec::new();
             for line in io::BufReader::new(file).lines() {
@@ -117,8 +409,273 @@ impl Tree {
             .into_iter()
             .map(|krate| serde_json::to_string(&krate))
             .collect::<Result<Vec<String>, _>>()?;
-        fs::write(path.as_path(), lines.join("\n") + "\n")?;
+        write_atomic(&path, (lines.join("\n") + "\n").as_bytes())?;
+
+        Ok(())
+    }
+
+    /// See [`Tree::replace_records`].
+    pub fn replace_records(&self, records: Vec<CrateVersion>) -> Result<(), Error> {
+        let mut crates = BTreeMap::<String, Vec<CrateVersion>>::new();
+        for record in records {
+            let name = record.name.to_lowercase();
+            crates.entry(name).or_default().push(record);
+        }
+
+        for entry in fs::read_dir(&self.tree.path)? {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            if file_name.starts_with('.') || file_name == "config.json" {
+                continue;
+            }
+            if entry.file_type()?.is_dir() {
+                fs::remove_dir_all(entry.path())?;
+            } else {
+                fs::remove_file(entry.path())?;
+            }
+        }
+
+        for (name, mut krates) in crates {
+            krates.sort_by(|a, b| a.version.cmp(&b.version));
+            let mut contents = Vec::new();
+            for krate in krates {
+                serde_json::to_writer(&mut contents, &krate)?;
+                writeln!(contents)?;
+            }
+            write_atomic(&self.tree.compute_record_path(&name), &contents)?;
+        }
 
         Ok(())
     }
 }
+
+/// Replaces the file at `path` so that readers and crashes only ever observe
+/// either the old or the new contents.
+///
+/// The contents go to a temporary file in the same directory first, which is
+/// synced to disk and renamed over `path` before the directory itself is synced.
+fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let dir = path.parent().expect("index files are never at the root");
+    fs::create_dir_all(dir)?;
+
+    let file_name = path.file_name().expect("index files have a name");
+    let temp_path = dir.join(format!(".{}{}", file_name.to_string_lossy(), TEMP_SUFFIX));
+    let result = (|| {
+        let mut file = fs::File::create(&temp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result?;
+
+    // make the rename itself durable
+    #[cfg(unix)]
+    fs::File::open(dir)?.sync_all()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Tree, LOCK_FILE};
+    use semver::Version;
+    use std::{fs, path::Path};
+    use valhall_models::crates::CrateVersion;
+
+    fn record(name: &str, version: Version) -> CrateVersion {
+        CrateVersion {
+            name: name.into(),
+            version,
+            dependencies: vec![],
+            checksum: "0".repeat(64),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+            rust_version: None,
+            features2: Default::default(),
+            v: None,
+        }
+    }
+
+    /// Lists all files below `dir`, relative to it.
+    fn files(dir: &Path) -> Vec<String> {
+        let mut out = Vec::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            if path.is_dir() {
+                out.extend(files(&path).into_iter().map(|f| format!("{}/{}", name, f)));
+            } else {
+                out.push(name);
+            }
+        }
+        out.sort();
+        out
+    }
+
+    #[test]
+    fn writes_leave_no_temporary_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let tree = Tree::new(dir.path().to_path_buf());
+        tree.add_record(record("abcd", Version::new(1, 0, 0)))
+            .unwrap();
+        tree.add_record(record("abcd", Version::new(1, 1, 0)))
+            .unwrap();
+        tree.alter_record("abcd", Version::new(1, 0, 0), |krate| krate.yanked = true)
+            .unwrap();
+
+        assert_eq!(files(dir.path()), vec![LOCK_FILE, "ab/cd/abcd"]);
+        let records = tree.all_records("abcd").unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records[0].yanked);
+    }
+
+    #[test]
+    fn crate_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let tree = Tree::new(dir.path().join("index"));
+        assert!(tree.crate_names().unwrap().is_empty());
+
+        for name in ["serde", "a", "syn", "ab"] {
+            tree.add_record(record(name, Version::new(1, 0, 0)))
+                .unwrap();
+        }
+        fs::write(dir.path().join("index/config.json"), "{}").unwrap();
+        fs::write(dir.path().join("index/3/s/other"), "").unwrap();
+
+        assert_eq!(tree.crate_names().unwrap(), vec!["a", "ab", "serde", "syn"]);
+    }
+
+    #[test]
+    fn normalize_record_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let tree = Tree::new(dir.path().to_path_buf());
+        let line = |name: &str, version: &str| {
+            format!(
+                r#"{{"name":"{}","vers":"{}","deps":[],"cksum":"00","features":{{}},"yanked":false}}"#,
+                name, version
+            )
+        };
+
+        // index files written before the paths were lowercased
+        fs::create_dir_all(dir.path().join("Se/rd")).unwrap();
+        fs::write(
+            dir.path().join("Se/rd/Serde"),
+            line("Serde", "1.0.0") + "\n",
+        )
+        .unwrap();
+        fs::create_dir_all(dir.path().join("AB/cd")).unwrap();
+        let old = line("ABcd", "1.0.0") + "\n" + &line("ABcd", "1.1.0") + "\n";
+        fs::write(dir.path().join("AB/cd/ABcd"), old).unwrap();
+        // the next publish created a second file at the lowercase path
+        tree.write_record_file("abcd", (line("ABcd", "1.1.0") + "\n").as_bytes())
+            .unwrap();
+        tree.add_record(record("syn", Version::new(1, 0, 0)))
+            .unwrap();
+
+        assert_eq!(
+            tree.normalize_record_paths().unwrap(),
+            vec!["ABcd", "Serde"]
+        );
+        assert_eq!(
+            files(dir.path()),
+            vec![LOCK_FILE, "3/s/syn", "ab/cd/abcd", "se/rd/serde"]
+        );
+        assert_eq!(tree.all_records("serde").unwrap().len(), 1);
+        let versions = tree
+            .all_records("abcd")
+            .unwrap()
+            .into_iter()
+            .map(|krate| krate.version.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(versions, vec!["1.1.0", "1.0.0"]);
+
+        assert!(tree.normalize_record_paths().unwrap().is_empty());
+    }
+
+    #[test]
+    fn add_record_keeps_existing_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let tree = Tree::new(dir.path().to_path_buf());
+        let line = r#"{"name":"abc","vers":"1.0.0","cksum":"00","rust_version":"1.70"}"#;
+        tree.write_record_file("abc", line.as_bytes()).unwrap();
+        tree.add_record(record("abc", Version::new(1, 1, 0)))
+            .unwrap();
+
+        let contents = fs::read_to_string(dir.path().join("3/a/abc")).unwrap();
+        assert!(contents.starts_with(&format!("{}\n", line)));
+        assert_eq!(tree.all_records("abc").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn concurrent_writers() {
+        let dir = tempfile::tempdir().unwrap();
+        // every thread opens the tree on its own, like separate processes would
+        let threads = (0..8)
+            .map(|thread| {
+                let tree = Tree::new(dir.path().to_path_buf());
+                std::thread::spawn(move || {
+                    for patch in 0..10 {
+                        tree.add_record(record("abcd", Version::new(1, thread, patch)))
+                            .unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let tree = Tree::new(dir.path().to_path_buf());
+        assert_eq!(tree.all_records("abcd").unwrap().len(), 80);
+    }
+
+    #[test]
+    fn record_path() {
+        assert_eq!(Tree::record_path("A"), "1/a");
+        assert_eq!(Tree::record_path("Ab"), "2/ab");
+        assert_eq!(Tree::record_path("ABC"), "3/a/abc");
+        assert_eq!(Tree::record_path("Serde_JSON"), "se/rd/serde_json");
+        assert_eq!(Tree::prefix("Serde_JSON"), "Se/rd");
+    }
+
+    #[test]
+    fn multibyte_names() {
+        assert_eq!(Tree::record_path("ä"), "1/ä");
+        assert_eq!(Tree::record_path("äbc"), "3/ä/äbc");
+        assert_eq!(Tree::record_path("ÄÖüß"), "äö/üß/äöüß");
+        assert_eq!(Tree::prefix("a€bcd"), "a€/bc");
+        assert_eq!(Tree::crate_name_from_path("3/ä/äbc"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let tree = Tree::new(dir.path().to_path_buf());
+        assert!(tree.read_record_file("a€bcd").is_err());
+        assert!(tree.all_records("äbc").is_err());
+    }
+
+    #[test]
+    fn crate_name_from_path() {
+        assert_eq!(Tree::crate_name_from_path("1/a"), Some("a"));
+        assert_eq!(Tree::crate_name_from_path("2/ab"), Some("ab"));
+        assert_eq!(Tree::crate_name_from_path("3/a/abc"), Some("abc"));
+        assert_eq!(Tree::crate_name_from_path("ab/cd/abcd"), Some("abcd"));
+        assert_eq!(
+            Tree::crate_name_from_path("se/rd/serde_json"),
+            Some("serde_json")
+        );
+
+        assert_eq!(Tree::crate_name_from_path("se/rd/Serde"), Some("Serde"));
+
+        assert_eq!(Tree::crate_name_from_path("abcd"), None);
+        assert_eq!(Tree::crate_name_from_path("2/a"), None);
+        assert_eq!(Tree::crate_name_from_path("3/b/abc"), None);
+        assert_eq!(Tree::crate_name_from_path("ab/ce/abcd"), None);
+        assert_eq!(Tree::crate_name_from_path("ab/cd/"), None);
+        assert_eq!(Tree::crate_name_from_path("../../etc/passwd"), None);
+        assert_eq!(Tree::crate_name_from_path("1/."), None);
+    }
+}
@@ -0,0 +1,294 @@
+//! A minimal, in-process implementation of the server side of the git smart-HTTP protocol.
+//!
+//! Only fetching (`git-upload-pack`) in protocol version 0 with stateless RPC is supported,
+//! which is what `git clone`, libgit2 and gitoxide use over HTTP.
+//! Shallow clones are not advertised, so clients always fetch the full history.
+
+use git2::{Oid, Repository};
+use std::io::Write;
+
+use super::error::{Error, IndexError};
+
+/// The capabilities advertised to clients.
+const CAPABILITIES: &str = "side-band-64k side-band ofs-delta no-progress agent=valhall";
+
+/// The maximum length of a pkt-line, including its 4 byte length prefix.
+const MAX_PKT_LEN: usize = 65520;
+
+/// Builds the response of `GET info/refs?service=git-upload-pack`.
+///
+/// `head` is the branch `HEAD` points to and the commit served for it,
+/// `None` if the repository has no commits yet.
+pub fn advertise_refs(head: Option<(&str, Oid)>) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_pkt_line(&mut out, b"# service=git-upload-pack\n");
+    write_flush(&mut out);
+
+    match head {
+        Some((name, oid)) => {
+            // the first advertised ref carries the capabilities
+            let line = format!("{} HEAD\0{} symref=HEAD:{}\n", oid, CAPABILITIES, name);
+            write_pkt_line(&mut out, line.as_bytes());
+            write_pkt_line(&mut out, format!("{} {}\n", oid, name).as_bytes());
+        }
+        None => {
+            // an empty repository still has to advertise its capabilities
+            let line = format!("{} capabilities^{{}}\0{}\n", Oid::zero(), CAPABILITIES);
+            write_pkt_line(&mut out, line.as_bytes());
+        }
+    }
+
+    write_flush(&mut out);
+    out
+}
+
+/// Builds the response of `POST git-upload-pack` for the negotiation request in `body`.
+///
+/// Only `head` (the advertised commit) and its ancestors may be fetched.
+pub fn upload_pack(repo: &Repository, body: &[u8], head: Option<Oid>) -> Result<Vec<u8>, Error> {
+    let request = UploadPackRequest::parse(body)?;
+    let mut out = Vec::new();
+    if request.wants.is_empty() {
+        return Ok(out);
+    }
+    for want in &request.wants {
+        let is_served = match head {
+            Some(head) => *want == head || repo.graph_descendant_of(head, *want)?,
+            None => false,
+        };
+        if !is_served {
+            return Err(protocol_error(&format!("not our ref {}", want)));
+        }
+    }
+
+    // the haves we also know are the common base of the pack
+    let odb = repo.odb()?;
+    let common = request
+        .haves
+        .iter()
+        .filter(|oid| odb.exists(**oid))
+        .copied()
+        .collect::<Vec<_>>();
+
+    // without multi_ack the first common commit is the only one acknowledged
+    match common.first() {
+        Some(oid) => write_pkt_line(&mut out, format!("ACK {}\n", oid).as_bytes()),
+        None => write_pkt_line(&mut out, b"NAK\n"),
+    }
+    if !request.done {
+        return Ok(out);
+    }
+
+    let mut walk = repo.revwalk()?;
+    for oid in &request.wants {
+        walk.push(*oid)?;
+    }
+    for oid in &common {
+        if repo.find_commit(*oid).is_ok() {
+            walk.hide(*oid)?;
+        }
+    }
+
+    let mut builder = repo.packbuilder()?;
+    builder.insert_walk(&mut walk)?;
+    let mut pack = git2::Buf::new();
+    builder.write_buf(&mut pack)?;
+
+    match request.sideband_len() {
+        Some(max_len) => {
+            for chunk in pack.chunks(max_len - 5) {
+                let mut data = Vec::with_capacity(chunk.len() + 1);
+                data.push(1);
+                data.extend_from_slice(chunk);
+                write_pkt_line(&mut out, &data);
+            }
+            write_flush(&mut out);
+        }
+        None => out.extend_from_slice(&pack),
+    }
+
+    Ok(out)
+}
+
+/// The parsed negotiation request of a client.
+#[derive(Debug, Default, PartialEq)]
+struct UploadPackRequest {
+    wants: Vec<Oid>,
+    haves: Vec<Oid>,
+    capabilities: Vec<String>,
+    done: bool,
+}
+
+impl UploadPackRequest {
+    fn parse(mut body: &[u8]) -> Result<Self, Error> {
+        let mut request = Self::default();
+
+        while !body.is_empty() {
+            let len = body
+                .get(..4)
+                .and_then(|len| std::str::from_utf8(len).ok())
+                .and_then(|len| usize::from_str_radix(len, 16).ok())
+                .ok_or_else(|| protocol_error("invalid pkt-line length"))?;
+            // flush-pkt, delim-pkt and response-end-pkt carry no data
+            if len < 4 {
+                body = &body[4..];
+                continue;
+            }
+            let line = body
+                .get(4..len)
+                .ok_or_else(|| protocol_error("truncated pkt-line"))?;
+            body = &body[len..];
+
+            let line = std::str::from_utf8(line)
+                .map_err(|_| protocol_error("pkt-line is not valid UTF-8"))?
+                .trim_end_matches('\n');
+            let mut parts = line.split(' ');
+            match parts.next() {
+                Some("want") => {
+                    request.wants.push(parse_oid(parts.next())?);
+                    request.capabilities.extend(parts.map(String::from));
+                }
+                Some("have") => request.haves.push(parse_oid(parts.next())?),
+                Some("done") => request.done = true,
+                Some(command) => {
+                    return Err(protocol_error(&format!(
+                        "unsupported command '{}'",
+                        command
+                    )))
+                }
+                None => {}
+            }
+        }
+
+        Ok(request)
+    }
+
+    /// The maximum pkt-line length for the side-band the client asked for, if any.
+    fn sideband_len(&self) -> Option<usize> {
+        let has = |cap: &str| self.capabilities.iter().any(|c| c == cap);
+        if has("side-band-64k") {
+            Some(MAX_PKT_LEN)
+        } else if has("side-band") {
+            Some(1000)
+        } else {
+            None
+        }
+    }
+}
+
+fn parse_oid(oid: Option<&str>) -> Result<Oid, Error> {
+    oid.and_then(|oid| Oid::from_str(oid).ok())
+        .ok_or_else(|| protocol_error("invalid object id"))
+}
+
+fn protocol_error(msg: &str) -> Error {
+    Error::from(IndexError::GitProtocol {
+        msg: msg.to_string(),
+    })
+}
+
+fn write_pkt_line(out: &mut Vec<u8>, data: &[u8]) {
+    write!(out, "{:04x}", data.len() + 4).expect("writing to a Vec cannot fail");
+    out.extend_from_slice(data);
+}
+
+fn write_flush(out: &mut Vec<u8>) {
+    out.extend_from_slice(b"0000");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{advertise_refs, upload_pack, write_flush, write_pkt_line, UploadPackRequest};
+    use git2::{Oid, Repository, Signature};
+    use std::path::Path;
+
+    fn commit(repo: &Repository, file: &str) -> Oid {
+        std::fs::write(repo.workdir().unwrap().join(file), file).unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new(file)).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        let parents = repo
+            .head()
+            .ok()
+            .and_then(|head| head.peel_to_commit().ok())
+            .into_iter()
+            .collect::<Vec<_>>();
+        let parents = parents.iter().collect::<Vec<_>>();
+        repo.commit(Some("HEAD"), &signature, &signature, file, &tree, &parents)
+            .unwrap()
+    }
+
+    fn request(lines: &[&str]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for line in lines {
+            match *line {
+                "" => write_flush(&mut body),
+                line => write_pkt_line(&mut body, format!("{}\n", line).as_bytes()),
+            }
+        }
+        body
+    }
+
+    #[test]
+    fn parse_request() {
+        let oid = "0123456789012345678901234567890123456789";
+        let body = request(&[
+            &format!("want {} side-band-64k ofs-delta", oid),
+            "",
+            &format!("have {}", oid),
+            "done",
+        ]);
+        let request = UploadPackRequest::parse(&body).unwrap();
+        assert_eq!(request.wants, vec![Oid::from_str(oid).unwrap()]);
+        assert_eq!(request.haves, vec![Oid::from_str(oid).unwrap()]);
+        assert_eq!(request.capabilities, vec!["side-band-64k", "ofs-delta"]);
+        assert!(request.done);
+
+        assert!(UploadPackRequest::parse(b"0009deepen").is_err());
+        assert!(UploadPackRequest::parse(b"00ff").is_err());
+    }
+
+    #[test]
+    fn advertise_empty_repository() {
+        let refs = String::from_utf8(advertise_refs(None)).unwrap();
+        assert!(refs.starts_with("001e# service=git-upload-pack\n0000"));
+        assert!(refs.contains(" capabilities^{}\0side-band-64k"));
+        assert!(refs.ends_with("0000"));
+    }
+
+    #[test]
+    fn advertise_and_fetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let first = commit(&repo, "a");
+        let second = commit(&repo, "b");
+
+        let refs = String::from_utf8(advertise_refs(Some(("refs/heads/main", second)))).unwrap();
+        assert!(refs.contains(&format!("{} HEAD\0", second)));
+        assert!(refs.contains("symref=HEAD:refs/heads/main"));
+        assert!(refs.contains(&format!("{} refs/heads/main", second)));
+
+        // a negotiation round without `done` only acknowledges
+        let body = request(&[&format!("want {}", second), "", &format!("have {}", first)]);
+        let response = upload_pack(&repo, &body, Some(second)).unwrap();
+        assert_eq!(response, format!("0031ACK {}\n", first).into_bytes());
+
+        // a full clone gets the pack through the side-band
+        let body = request(&[&format!("want {} side-band-64k", second), "", "done"]);
+        let response = upload_pack(&repo, &body, Some(second)).unwrap();
+        assert!(response.starts_with(b"0008NAK\n"));
+        assert_eq!(&response[12..17], b"\x01PACK");
+        assert!(response.ends_with(b"0000"));
+
+        // without a side-band the raw pack follows the acknowledgement
+        let body = request(&[&format!("want {}", second), "", "done"]);
+        let response = upload_pack(&repo, &body, Some(second)).unwrap();
+        assert_eq!(&response[8..12], b"PACK");
+
+        // commits that are not advertised cannot be fetched
+        let body = request(&[&format!("want {}", second), "", "done"]);
+        assert!(upload_pack(&repo, &body, Some(first)).is_err());
+        assert!(upload_pack(&repo, &body, None).is_err());
+    }
+}
@@ -27,6 +27,9 @@ pub struct CrateMetadata {
     pub repository: Option<String>,
     pub badges: Option<HashMap<String, HashMap<String, String>>>,
     pub links: Option<String>,
+    /// The minimum supported Rust version of the crate.
+    #[serde(default)]
+    pub rust_version: Option<String>,
 }
 
 /// Represents a crate version record.
@@ -61,6 +64,38 @@ pub struct CrateVersion {
     /// Related links about the crate.
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub links: Option<String>,
+
+    /// The minimum supported Rust version of the crate (eg. "1.70").
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub rust_version: Option<String>,
+
+    /// The features using the namespaced (`dep:`) or weak (`?/`) dependency syntax.
+    ///
+    /// They are kept apart from `features` so that older cargo versions,
+    /// which do not understand the syntax, can still use the other features.
+    #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    pub features2: HashMap<String, Vec<String>>,
+
+    /// The version of the index schema of this record.
+    ///
+    /// If not specified, the record has version 1. Records with `features2` have version 2.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub v: Option<u32>,
+}
+
+impl CrateVersion {
+    /// Sets the features of the crate, moving the features that need
+    /// index schema version 2 into `features2`.
+    pub fn set_features(&mut self, features: HashMap<String, Vec<String>>) {
+        let (features2, features) = features.into_iter().partition(|(_, enables)| {
+            enables
+                .iter()
+                .any(|enabled| enabled.starts_with("dep:") || enabled.contains("?/"))
+        });
+        self.features = features;
+        self.features2 = features2;
+        self.v = (!self.features2.is_empty()).then_some(2);
+    }
 }
 
 /// Represents a crate dependency.
@@ -122,14 +157,92 @@ pub enum CrateDependencyKind {
 
 impl From<CrateMetadata> for CrateVersion {
     fn from(value: CrateMetadata) -> Self {
-        Self {
+        let mut record = Self {
             name: value.name,
             version: value.version,
             dependencies: value.dependencies,
             checksum: "FIXME".into(),
-            features: value.features,
+            features: HashMap::new(),
             yanked: false,
             links: value.links,
+            rust_version: value.rust_version,
+            features2: HashMap::new(),
+            v: None,
+        };
+        record.set_features(value.features);
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CrateMetadata, CrateVersion};
+    use serde_json::Value;
+
+    /// Index lines as written by crates.io.
+    const INDEX_LINES: &[&str] = &[
+        r#"{"name":"cfg-if","vers":"1.0.0","deps":[{"name":"compiler_builtins","req":"^0.1.2","features":[],"optional":true,"default_features":true,"target":null,"kind":"normal"},{"name":"core","req":"^1.0.0","features":[],"optional":true,"default_features":true,"target":null,"kind":"normal","package":"rustc-std-workspace-core"}],"cksum":"baf1de4339761588bc0619e3cbc0120ee582ebb74b53b4efbf79117bd2da40fd","features":{"rustc-dep-of-std":["core","compiler_builtins"]},"yanked":false,"links":null}"#,
+        r#"{"name":"serde","vers":"1.0.210","deps":[{"name":"serde_derive","req":"=1.0.210","features":[],"optional":true,"default_features":true,"target":null,"kind":"normal"},{"name":"serde_derive","req":"=1.0.210","features":[],"optional":false,"default_features":true,"target":"cfg(any())","kind":"normal"},{"name":"serde_derive","req":"^1","features":[],"optional":false,"default_features":true,"target":null,"kind":"dev"}],"cksum":"c8e3592472072e6e22e0a54d5904d9febf8508f65fb8552499a1abc7d1078c3a","features":{"alloc":[],"default":["std"],"derive":["serde_derive"],"rc":[],"std":[],"unstable":[]},"yanked":false,"rust_version":"1.31"}"#,
+        r#"{"name":"indexmap","vers":"2.6.0","deps":[{"name":"borsh","req":"^1.2","features":[],"optional":true,"default_features":false,"target":null,"kind":"normal"},{"name":"equivalent","req":"^1.0","features":[],"optional":false,"default_features":false,"target":null,"kind":"normal"},{"name":"hashbrown","req":"^0.15.0","features":[],"optional":false,"default_features":false,"target":null,"kind":"normal"},{"name":"serde","req":"^1.0","features":[],"optional":true,"default_features":false,"target":null,"kind":"normal"}],"cksum":"707907fe3c25f5424cce2cb7e1cbcafee6bdbe735ca90ef77c29e84591e5b9da","features":{"default":["std"],"std":[],"test_debug":[]},"yanked":false,"rust_version":"1.63","features2":{"borsh":["dep:borsh"],"serde":["dep:serde","hashbrown?/serde"]},"v":2}"#,
+    ];
+
+    /// Removes all `null` values, which are equivalent to missing fields in the index.
+    fn without_nulls(value: Value) -> Value {
+        match value {
+            Value::Object(map) => Value::Object(
+                map.into_iter()
+                    .filter(|(_, value)| !value.is_null())
+                    .map(|(key, value)| (key, without_nulls(value)))
+                    .collect(),
+            ),
+            Value::Array(values) => Value::Array(values.into_iter().map(without_nulls).collect()),
+            value => value,
+        }
+    }
+
+    #[test]
+    fn index_line_round_trip() {
+        for line in INDEX_LINES {
+            let record: CrateVersion = serde_json::from_str(line).unwrap();
+            let written = serde_json::to_value(&record).unwrap();
+            let original: Value = serde_json::from_str(line).unwrap();
+            assert_eq!(written, without_nulls(original), "{}", record.name);
         }
+
+        let record: CrateVersion = serde_json::from_str(INDEX_LINES[2]).unwrap();
+        assert_eq!(record.rust_version.as_deref(), Some("1.63"));
+        assert_eq!(record.features2["borsh"], vec!["dep:borsh"]);
+        assert_eq!(record.v, Some(2));
+    }
+
+    #[test]
+    fn split_features_from_metadata() {
+        let metadata: CrateMetadata = serde_json::from_value(serde_json::json!({
+            "name": "abcd",
+            "vers": "1.0.0",
+            "deps": [],
+            "features": {
+                "default": ["std"],
+                "std": [],
+                "serde": ["dep:serde"],
+                "json": ["serde_json?/std", "std"],
+            },
+            "authors": [],
+            "rust_version": "1.70",
+        }))
+        .unwrap();
+
+        let record = CrateVersion::from(metadata);
+        assert_eq!(record.rust_version.as_deref(), Some("1.70"));
+        assert_eq!(record.features.len(), 2);
+        assert_eq!(record.features["default"], vec!["std"]);
+        assert_eq!(record.features2.len(), 2);
+        assert_eq!(record.features2["json"], vec!["serde_json?/std", "std"]);
+        assert_eq!(record.v, Some(2));
+
+        let mut record = record;
+        record.set_features([("default".to_string(), vec![])].into());
+        assert!(record.features2.is_empty());
+        assert_eq!(record.v, None);
     }
 }
@@ -1,5 +1,6 @@
 pub mod account;
 pub mod crates;
+pub mod name;
 
 #[derive(Debug)]
 pub struct Author {
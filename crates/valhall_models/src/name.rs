@@ -0,0 +1,90 @@
+//! The rules for crate names, which follow those of cargo and crates.io.
+
+use thiserror::Error;
+
+/// The maximum length of a crate name.
+pub const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum NameError {
+    #[error("the crate name must not be empty")]
+    Empty,
+    #[error("the crate name '{0}' is longer than {} characters", MAX_NAME_LENGTH)]
+    TooLong(String),
+    #[error("the crate name '{0}' has to start with an ASCII letter")]
+    InvalidStart(String),
+    #[error("the crate name '{name}' contains the invalid character '{invalid}', only ASCII letters, digits, '-' and '_' are allowed")]
+    InvalidCharacter { name: String, invalid: char },
+}
+
+/// Checks that `name` is a valid crate name.
+pub fn validate_crate_name(name: &str) -> Result<(), NameError> {
+    let Some(first) = name.chars().next() else {
+        return Err(NameError::Empty);
+    };
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(NameError::TooLong(name.to_string()));
+    }
+    if !first.is_ascii_alphabetic() {
+        return Err(NameError::InvalidStart(name.to_string()));
+    }
+    if let Some(invalid) = name
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && *c != '-' && *c != '_')
+    {
+        return Err(NameError::InvalidCharacter {
+            name: name.to_string(),
+            invalid,
+        });
+    }
+    Ok(())
+}
+
+/// Returns the canonical spelling of a crate name.
+///
+/// Names that only differ in case or in `-` and `_` have the same canonical spelling
+/// and refer to the same crate.
+pub fn canonical_crate_name(name: &str) -> String {
+    name.to_lowercase().replace('_', "-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{canonical_crate_name, validate_crate_name, NameError};
+
+    #[test]
+    fn validate() {
+        for name in ["a", "serde", "Serde_JSON", "tokio-1", &"a".repeat(64)] {
+            assert_eq!(validate_crate_name(name), Ok(()), "{}", name);
+        }
+
+        assert_eq!(validate_crate_name(""), Err(NameError::Empty));
+        assert!(matches!(
+            validate_crate_name(&"a".repeat(65)),
+            Err(NameError::TooLong(_))
+        ));
+        for name in ["1abc", "_abc", "-abc", "äbc"] {
+            assert!(matches!(
+                validate_crate_name(name),
+                Err(NameError::InvalidStart(_))
+            ));
+        }
+        assert_eq!(
+            validate_crate_name("ab.c"),
+            Err(NameError::InvalidCharacter {
+                name: "ab.c".into(),
+                invalid: '.'
+            })
+        );
+        assert!(validate_crate_name("ab/../c").is_err());
+    }
+
+    #[test]
+    fn canonical() {
+        assert_eq!(canonical_crate_name("Foo_Bar-baz"), "foo-bar-baz");
+        assert_eq!(
+            canonical_crate_name("foo_bar"),
+            canonical_crate_name("FOO-BAR")
+        );
+    }
+}
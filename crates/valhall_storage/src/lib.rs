@@ -1,15 +1,36 @@
+use std::io::Write;
 use std::path::PathBuf;
 
 use semver::Version;
+use tempfile::NamedTempFile;
 
 #[derive(Debug, Clone)]
 pub struct Storage {
     pub path: PathBuf,
 }
 
+/// A `.crate` file in the storage.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Crate {
     pub name: String,
-    pub version: String,
+    pub version: Version,
+}
+
+/// A `.crate` file written by [`Storage::stage_crate`] that is not stored yet.
+///
+/// Dropping it without persisting it removes the temporary file.
+#[derive(Debug)]
+pub struct StagedCrate {
+    temp: NamedTempFile,
+    path: PathBuf,
+}
+
+impl StagedCrate {
+    /// Moves the file to its final path in the storage.
+    pub fn persist(self) -> std::io::Result<()> {
+        self.temp.persist(&self.path).map_err(|err| err.error)?;
+        Ok(())
+    }
 }
 
 impl Storage {
@@ -23,30 +44,72 @@ impl Storage {
         std::fs::write(path, bytes)
     }
 
+    /// Writes a `.crate` file to a temporary location next to its final path.
+    ///
+    /// The file only becomes visible once [`StagedCrate::persist`] is called.
+    pub fn stage_crate(
+        &self,
+        name: &str,
+        version: &Version,
+        bytes: &[u8],
+    ) -> std::io::Result<StagedCrate> {
+        let path = self.get_path(name, version);
+        let dir = path.parent().unwrap();
+        std::fs::create_dir_all(dir)?;
+        // concurrent uploads of the same version must not share a temporary file
+        let mut temp = tempfile::Builder::new()
+            .prefix(".")
+            .suffix(".crate.tmp")
+            .tempfile_in(dir)?;
+        temp.write_all(bytes)?;
+        Ok(StagedCrate { temp, path })
+    }
+
+    /// Removes a stored `.crate` file.
+    pub fn remove_crate(&self, name: &str, version: &Version) -> std::io::Result<()> {
+        std::fs::remove_file(self.get_path(name, version))
+    }
+
     pub fn get_crate(&self, name: &str, version: Version) -> std::io::Result<Vec<u8>> {
         let path = self.get_path(name, &version);
         std::fs::read(path)
     }
 
-    pub fn get_all_crates(&self) -> Vec<Crate> {
-        self.path
-            .read_dir()
-            .unwrap()
-            .into_iter()
-            .map(|e| {
-                e.unwrap()
-                    .path()
-                    .file_stem()
-                    .unwrap()
+    /// Lists all stored `.crate` files, ordered by name and version.
+    ///
+    /// Files that do not follow the `{name}/{name}-{version}.crate` layout are skipped.
+    pub fn get_all_crates(&self) -> std::io::Result<Vec<Crate>> {
+        let mut crates = Vec::new();
+        let dirs = match self.path.read_dir() {
+            Ok(dirs) => dirs,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(crates),
+            Err(err) => return Err(err),
+        };
+
+        for dir in dirs {
+            let dir = dir?;
+            if !dir.file_type()?.is_dir() {
+                continue;
+            }
+            let name = dir.file_name().to_string_lossy().to_string();
+            for file in dir.path().read_dir()? {
+                let file_name = file?.file_name();
+                let version = file_name
                     .to_str()
-                    .unwrap()
-                    .to_owned()
-            })
-            .map(|n| Crate {
-                name: n,
-                version: "1.2.3-todo.1".into(),
-            })
-            .collect()
+                    .and_then(|file_name| file_name.strip_prefix(&format!("{}-", name)))
+                    .and_then(|rest| rest.strip_suffix(".crate"))
+                    .and_then(|version| Version::parse(version).ok());
+                if let Some(version) = version {
+                    crates.push(Crate {
+                        name: name.clone(),
+                        version,
+                    });
+                }
+            }
+        }
+
+        crates.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+        Ok(crates)
     }
 
     #[inline]
@@ -0,0 +1,2 @@
+-- the yanked state of a version, so the index can be rebuilt from the stored crates
+alter table crate_versions add column `yanked` boolean not null default false;
@@ -0,0 +1,2 @@
+-- names that only differ in case or in `-` and `_` refer to the same crate
+create unique index if not exists crates_canonical_name on crates (lower(replace(`name`, '_', '-')));
@@ -0,0 +1,21 @@
+-- the full metadata of every published version, lists and maps are stored as json
+alter table crate_versions add column `description` varchar(4096);
+alter table crate_versions add column `homepage` varchar(1024);
+alter table crate_versions add column `documentation` varchar(1024);
+alter table crate_versions add column `repository` varchar(1024);
+alter table crate_versions add column `license` varchar(255);
+alter table crate_versions add column `license_file` varchar(1024);
+alter table crate_versions add column `readme` text;
+alter table crate_versions add column `readme_file` varchar(1024);
+alter table crate_versions add column `links` varchar(255);
+alter table crate_versions add column `rust_version` varchar(32);
+alter table crate_versions add column `authors` text not null default '[]';
+alter table crate_versions add column `keywords` text not null default '[]';
+alter table crate_versions add column `categories` text not null default '[]';
+alter table crate_versions add column `badges` text not null default '{}';
+
+-- the metadata of the latest version is shown for the crate
+alter table crates add column `homepage` varchar(1024);
+alter table crates add column `license` varchar(255);
+alter table crates add column `keywords` text not null default '[]';
+alter table crates add column `categories` text not null default '[]';
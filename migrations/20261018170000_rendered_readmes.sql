@@ -0,0 +1,2 @@
+-- the README of every version, rendered to sanitized html
+alter table crate_versions add column `readme_html` text;
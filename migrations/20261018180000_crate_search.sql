@@ -0,0 +1,24 @@
+-- full-text search over the names, descriptions and keywords of the crates
+create virtual table if not exists crates_fts using fts5(
+    `name`, `description`, `keywords`,
+    content = 'crates', content_rowid = 'id'
+);
+
+create trigger if not exists crates_fts_insert after insert on crates begin
+    insert into crates_fts (rowid, name, description, keywords)
+    values (new.id, new.name, new.description, new.keywords);
+end;
+
+create trigger if not exists crates_fts_delete after delete on crates begin
+    insert into crates_fts (crates_fts, rowid, name, description, keywords)
+    values ('delete', old.id, old.name, old.description, old.keywords);
+end;
+
+create trigger if not exists crates_fts_update after update on crates begin
+    insert into crates_fts (crates_fts, rowid, name, description, keywords)
+    values ('delete', old.id, old.name, old.description, old.keywords);
+    insert into crates_fts (rowid, name, description, keywords)
+    values (new.id, new.name, new.description, new.keywords);
+end;
+
+insert into crates_fts (crates_fts) values ('rebuild');
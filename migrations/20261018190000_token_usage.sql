@@ -0,0 +1,3 @@
+-- when api tokens were created and last used
+alter table tokens add column `created_at` integer not null default 0;
+alter table tokens add column `last_used_at` integer;
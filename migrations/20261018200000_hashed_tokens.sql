@@ -0,0 +1,6 @@
+-- api tokens are stored as sha-256 digests of their secrets
+alter table tokens rename column `token` to `token_hash`;
+
+-- the existing tokens are hashed when the registry starts
+alter table tokens add column `plaintext` boolean not null default false;
+update tokens set `plaintext` = true;
@@ -0,0 +1,3 @@
+-- api tokens may expire and be limited to crates matching patterns (a json array, null for all crates)
+alter table tokens add column `expires_at` integer;
+alter table tokens add column `crate_scopes` text;
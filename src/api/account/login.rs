@@ -1 +1,48 @@
-pub async fn handler() {}
+use axum::{extract::State, Json};
+use axum_extra::extract::CookieJar;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::error::ApiError2,
+    app::App,
+    auth::{account, session},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct LoginRequest {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoginResponse {
+    pub ok: bool,
+}
+
+/// Starts a session and stores its token in the session cookie.
+pub async fn handler(
+    State(app): State<App>,
+    jar: CookieJar,
+    Json(request): Json<LoginRequest>,
+) -> Result<(CookieJar, Json<LoginResponse>), ApiError2> {
+    let user_id = account::authenticate(&app.db, &request.email, &request.password).await?;
+    let token = session::create(&app.db, user_id).await?;
+    Ok((
+        jar.add(session::cookie(token, app.secure_cookies)),
+        Json(LoginResponse { ok: true }),
+    ))
+}
+
+/// Ends the session of the session cookie and removes the cookie.
+pub async fn logout_handler(
+    State(app): State<App>,
+    jar: CookieJar,
+) -> Result<(CookieJar, Json<LoginResponse>), ApiError2> {
+    if let Some(cookie) = jar.get(session::SESSION_COOKIE) {
+        session::delete(&app.db, cookie.value()).await?;
+    }
+    Ok((
+        jar.remove(session::removal_cookie()),
+        Json(LoginResponse { ok: true }),
+    ))
+}
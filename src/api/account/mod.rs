@@ -1,2 +1,3 @@
 pub(crate) mod login;
 pub(crate) mod register;
+pub(crate) mod tokens;
@@ -1 +1,26 @@
-pub async fn handler() {}
+use axum::{extract::State, Json};
+use serde::{Deserialize, Serialize};
+
+use crate::{api::error::ApiError2, app::App, auth::account};
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterRequest {
+    pub email: String,
+    pub name: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RegisterResponse {
+    pub ok: bool,
+    pub id: i64,
+}
+
+pub async fn handler(
+    State(app): State<App>,
+    Json(request): Json<RegisterRequest>,
+) -> Result<Json<RegisterResponse>, ApiError2> {
+    let id = account::register(&app.db, &request.email, &request.name, &request.password).await?;
+    tracing::info!("registered the account of '{}'", request.email.trim());
+    Ok(Json(RegisterResponse { ok: true, id }))
+}
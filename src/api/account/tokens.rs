@@ -0,0 +1,120 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::error::ApiError2,
+    app::App,
+    auth::{
+        backend::{NewToken, Scope, Token},
+        RequireAuth,
+    },
+};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    /// The names of the scopes of the token, e.g. `publish-new`.
+    pub endpoint_scopes: Vec<String>,
+    /// The patterns of the crates the token may be used for, e.g. `my-team-*`.
+    #[serde(default)]
+    pub crate_scopes: Option<Vec<String>>,
+    /// When the token expires, it is valid forever if missing.
+    #[serde(default)]
+    pub expired_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiToken {
+    pub id: i64,
+    pub name: String,
+    /// The secret of the token, only returned when it is created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    pub endpoint_scopes: Vec<String>,
+    pub crate_scopes: Option<Vec<String>>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expired_at: Option<DateTime<Utc>>,
+}
+
+impl From<Token> for ApiToken {
+    fn from(token: Token) -> Self {
+        Self {
+            id: token.id,
+            name: token.name,
+            token: None,
+            endpoint_scopes: token.scope.names(),
+            crate_scopes: token.crate_scopes,
+            created_at: DateTime::from_timestamp(token.created_at, 0),
+            last_used_at: token
+                .last_used_at
+                .and_then(|seconds| DateTime::from_timestamp(seconds, 0)),
+            expired_at: token
+                .expires_at
+                .and_then(|seconds| DateTime::from_timestamp(seconds, 0)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateTokenResponse {
+    pub api_token: ApiToken,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListTokensResponse {
+    pub api_tokens: Vec<ApiToken>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevokeTokenResponse {
+    pub ok: bool,
+}
+
+pub async fn create_handler(
+    State(app): State<App>,
+    auth: Option<RequireAuth>,
+    Json(request): Json<CreateTokenRequest>,
+) -> Result<Json<CreateTokenResponse>, ApiError2> {
+    let auth = auth.ok_or(ApiError2::NotLoggedIn)?;
+    let mut scope = Scope::empty();
+    for name in &request.endpoint_scopes {
+        scope |= name.parse::<Scope>()?;
+    }
+
+    let new = NewToken {
+        name: request.name,
+        scope,
+        crate_scopes: request.crate_scopes,
+        expires_at: request.expired_at.map(|time| time.timestamp()),
+    };
+    let (token, secret) = Token::create(&app.db, auth.user_id, new).await?;
+    let mut api_token = ApiToken::from(token);
+    api_token.token = Some(secret);
+    Ok(Json(CreateTokenResponse { api_token }))
+}
+
+pub async fn list_handler(
+    State(app): State<App>,
+    auth: Option<RequireAuth>,
+) -> Result<Json<ListTokensResponse>, ApiError2> {
+    let auth = auth.ok_or(ApiError2::NotLoggedIn)?;
+    let tokens = Token::list(&app.db, auth.user_id).await?;
+    Ok(Json(ListTokensResponse {
+        api_tokens: tokens.into_iter().map(ApiToken::from).collect(),
+    }))
+}
+
+pub async fn delete_handler(
+    State(app): State<App>,
+    auth: Option<RequireAuth>,
+    Path(id): Path<i64>,
+) -> Result<Json<RevokeTokenResponse>, ApiError2> {
+    let auth = auth.ok_or(ApiError2::NotLoggedIn)?;
+    Token::revoke(&app.db, auth.user_id, id).await?;
+    Ok(Json(RevokeTokenResponse { ok: true }))
+}
@@ -3,6 +3,7 @@ use axum::{
     extract::{Path, State},
 };
 use semver::Version;
+use std::io::ErrorKind;
 
 use crate::{app::App, error::ApiError};
 
@@ -12,7 +13,25 @@ pub async fn handler(
 ) -> Result<Bytes, ApiError> {
     tracing::info!("Download request for crate '{} ({})'", name, version);
 
-    let crate_bytes = app.storage.get_crate(&name, Version::parse(&version)?)?;
+    let parsed_version = Version::parse(&version)?;
+    // crates are stored under the name they were first published with
+    let name = match app.db.find_crate(&name).await? {
+        Some((_, actual)) => actual,
+        None => name,
+    };
+    let crate_bytes = match app.storage.get_crate(&name, parsed_version.clone()) {
+        Ok(bytes) => bytes,
+        // crates not published locally are fetched from upstream in proxy mode
+        Err(err) if err.kind() == ErrorKind::NotFound => match &app.proxy {
+            Some(proxy) => {
+                proxy
+                    .fetch_crate(&app.index, &app.storage, &name, &parsed_version)
+                    .await?
+            }
+            None => return Err(err.into()),
+        },
+        Err(err) => return Err(err.into()),
+    };
 
     // update download counter for the crate+version
     sqlx::query(
@@ -1,3 +1,258 @@
-pub async fn handler() {
-    tracing::info!("Info was requested!");
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use semver::Version;
+use serde::Serialize;
+use sqlx::FromRow;
+use std::collections::HashMap;
+use valhall_index::IndexTrait;
+
+use super::owners::OwnerEntry;
+use crate::{api::error::ApiError2, app::App, search};
+
+/// The details of a crate, shaped like the response of crates.io.
+#[derive(Debug, Serialize)]
+pub struct CrateResponse {
+    #[serde(rename = "crate")]
+    pub krate: CrateInfo,
+    /// All versions, the newest first.
+    pub versions: Vec<VersionInfo>,
+    pub owners: Vec<OwnerEntry>,
+}
+
+/// The metadata of a crate, taken from its latest version.
+#[derive(Debug, Serialize)]
+pub struct CrateInfo {
+    pub id: i64,
+    pub name: String,
+    pub max_version: String,
+    /// The highest version that is neither yanked nor a pre-release.
+    pub max_stable_version: Option<String>,
+    /// The most recently published version.
+    pub newest_version: String,
+    pub description: Option<String>,
+    pub homepage: Option<String>,
+    pub documentation: Option<String>,
+    pub repository: Option<String>,
+    pub license: Option<String>,
+    pub keywords: Vec<String>,
+    pub categories: Vec<String>,
+    /// The downloads of all versions.
+    pub downloads: i64,
+    pub created_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+    /// The ids of all versions.
+    pub versions: Vec<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub id: i64,
+    #[serde(rename = "crate")]
+    pub krate: String,
+    pub num: String,
+    pub dl_path: String,
+    /// The SHA256 checksum of the `.crate` file, if the version is in the index.
+    pub checksum: Option<String>,
+    pub yanked: bool,
+    pub created_at: Option<DateTime<Utc>>,
+    pub downloads: i64,
+    pub license: Option<String>,
+    pub rust_version: Option<String>,
+}
+
+#[derive(FromRow)]
+struct CrateRow {
+    id: i64,
+    name: String,
+    description: Option<String>,
+    homepage: Option<String>,
+    documentation: Option<String>,
+    repository: Option<String>,
+    license: Option<String>,
+    keywords: String,
+    categories: String,
+}
+
+#[derive(FromRow)]
+struct VersionRow {
+    id: i64,
+    version: String,
+    yanked: bool,
+    created_at: i64,
+    downloads: i64,
+    license: Option<String>,
+    rust_version: Option<String>,
+}
+
+pub async fn handler(
+    State(app): State<App>,
+    Path(name): Path<String>,
+) -> Result<Json<CrateResponse>, ApiError2> {
+    tracing::info!("Info was requested for crate '{}'", name);
+
+    let (id, _) = app
+        .db
+        .find_crate(&name)
+        .await?
+        .ok_or(ApiError2::CrateNotFound(name))?;
+    let row: CrateRow = sqlx::query_as("SELECT * FROM crates WHERE id = $1")
+        .bind(id)
+        .fetch_one(&app.db.pool)
+        .await?;
+
+    let mut versions: Vec<VersionRow> = sqlx::query_as(
+        "SELECT id, version, yanked, created_at, downloads, license, rust_version
+        FROM crate_versions WHERE name = $1",
+    )
+    .bind(&row.name)
+    .fetch_all(&app.db.pool)
+    .await?;
+    versions.sort_by_cached_key(|version| std::cmp::Reverse(Version::parse(&version.version).ok()));
+
+    let owners: Vec<OwnerEntry> = sqlx::query_as(
+        "SELECT id, name, email FROM users WHERE id IN (
+            SELECT user_id FROM crate_owners WHERE crate_id = $1
+        )",
+    )
+    .bind(id)
+    .fetch_all(&app.db.pool)
+    .await?;
+
+    // the checksums are only recorded in the index
+    let checksums: HashMap<String, String> = app
+        .index
+        .all_records(&row.name)
+        .map(|records| {
+            records
+                .into_iter()
+                .map(|record| (record.version.to_string(), record.checksum))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let yanked: Vec<(String, bool)> = versions
+        .iter()
+        .map(|version| (version.version.clone(), version.yanked))
+        .collect();
+    let max_stable_version = versions
+        .iter()
+        .filter(|version| !version.yanked)
+        .filter_map(|version| Version::parse(&version.version).ok())
+        .filter(|version| version.pre.is_empty())
+        .max()
+        .map(|version| version.to_string());
+    let newest = versions.iter().max_by_key(|version| version.created_at);
+    let timestamp = |seconds: i64| DateTime::from_timestamp(seconds, 0);
+
+    Ok(Json(CrateResponse {
+        krate: CrateInfo {
+            id: row.id,
+            max_version: search::max_version(&yanked),
+            max_stable_version,
+            newest_version: newest
+                .map(|version| version.version.clone())
+                .unwrap_or_default(),
+            description: row.description,
+            homepage: row.homepage,
+            documentation: row.documentation,
+            repository: row.repository,
+            license: row.license,
+            keywords: serde_json::from_str(&row.keywords)?,
+            categories: serde_json::from_str(&row.categories)?,
+            downloads: versions.iter().map(|version| version.downloads).sum(),
+            created_at: versions
+                .iter()
+                .map(|version| version.created_at)
+                .min()
+                .and_then(timestamp),
+            updated_at: newest.and_then(|version| timestamp(version.created_at)),
+            versions: versions.iter().map(|version| version.id).collect(),
+            name: row.name.clone(),
+        },
+        versions: versions
+            .into_iter()
+            .map(|version| VersionInfo {
+                id: version.id,
+                dl_path: format!("/api/v1/crates/{}/{}/download", row.name, version.version),
+                checksum: checksums.get(&version.version).cloned(),
+                krate: row.name.clone(),
+                num: version.version,
+                yanked: version.yanked,
+                created_at: timestamp(version.created_at),
+                downloads: version.downloads,
+                license: version.license,
+                rust_version: version.rust_version,
+            })
+            .collect(),
+        owners,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::handler;
+    use crate::{api::error::ApiError2, app::AppState};
+    use axum::extract::{Path, State};
+    use std::sync::Arc;
+    use valhall_index::IndexTrait;
+    use valhall_models::crates::CrateVersion;
+
+    #[tokio::test]
+    async fn crate_details() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = AppState::for_tests(dir.path()).await;
+        for statement in [
+            "INSERT INTO users (id, email, name, password) VALUES (1, 'a@b.c', 'alice', '')",
+            r#"INSERT INTO crates (id, name, description, license, keywords) VALUES (1, 'ab_cd', 'abc', 'MIT', '["x"]')"#,
+            "INSERT INTO crate_owners (crate_id, user_id) VALUES (1, 1)",
+            "INSERT INTO crate_versions (name, version, created_at, downloads, yanked, rust_version)
+                VALUES ('ab_cd', '1.0.0', 100, 3, false, '1.70'),
+                    ('ab_cd', '1.1.0', 200, 4, true, null),
+                    ('ab_cd', '2.0.0-rc.1', 300, 5, false, null),
+                    ('ab_cd', '0.9.0', 400, 1, false, null)",
+        ] {
+            sqlx::query(statement).execute(&app.db.pool).await.unwrap();
+        }
+        let record: CrateVersion = serde_json::from_value(serde_json::json!({
+            "name": "ab_cd",
+            "vers": "1.0.0",
+            "cksum": "ff",
+        }))
+        .unwrap();
+        app.index.add_record(record).unwrap();
+        let app = Arc::new(app);
+
+        let info = handler(State(app.clone()), Path("AB-CD".into()))
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(info.krate.name, "ab_cd");
+        assert_eq!(info.krate.max_version, "2.0.0-rc.1");
+        assert_eq!(info.krate.max_stable_version.as_deref(), Some("1.0.0"));
+        assert_eq!(info.krate.newest_version, "0.9.0");
+        assert_eq!(info.krate.downloads, 13);
+        assert_eq!(info.krate.keywords, vec!["x"]);
+        assert_eq!(info.krate.created_at.unwrap().timestamp(), 100);
+        assert_eq!(info.krate.updated_at.unwrap().timestamp(), 400);
+
+        let versions: Vec<&str> = info.versions.iter().map(|v| v.num.as_str()).collect();
+        assert_eq!(versions, vec!["2.0.0-rc.1", "1.1.0", "1.0.0", "0.9.0"]);
+        assert!(info.versions[1].yanked);
+        assert_eq!(info.versions[2].checksum.as_deref(), Some("ff"));
+        assert_eq!(info.versions[2].rust_version.as_deref(), Some("1.70"));
+        assert_eq!(info.versions[0].checksum, None);
+        assert_eq!(
+            info.versions[0].dl_path,
+            "/api/v1/crates/ab_cd/2.0.0-rc.1/download"
+        );
+        assert_eq!(info.owners[0].name, "alice");
+
+        let err = handler(State(app), Path("missing".into()))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ApiError2::CrateNotFound(_)));
+    }
 }
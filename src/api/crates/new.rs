@@ -1,19 +1,28 @@
 use axum::{body::Bytes, extract::State, Json};
-use semver::VersionReq;
+use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
 use std::io::Cursor;
 use tokio::io::{AsyncReadExt, BufReader};
 
 use crate::{
     api::error::ApiError2,
-    app::App,
+    app::{App, AppState},
     auth::{
         backend::{Scope, Token},
         Auth,
     },
+    readme,
+    tarball::{self, TarballError},
 };
 use valhall_index::IndexTrait;
-use valhall_models::crates::{CrateMetadata, CrateVersion};
+use valhall_models::{
+    crates::{CrateMetadata, CrateVersion},
+    name::validate_crate_name,
+};
+
+/// The maximum size of the JSON metadata of an upload (which includes the README) in bytes.
+pub const MAX_METADATA_SIZE: u32 = 4 * 1024 * 1024;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PublishResponse {}
@@ -34,12 +43,22 @@ pub async fn handler(
 
     // extract metadata from the request body
     let metadata_size = reader.read_u32_le().await?;
+    if metadata_size > MAX_METADATA_SIZE {
+        return Err(ApiError2::MetadataTooLarge(MAX_METADATA_SIZE));
+    }
     let mut metadata_bytes = vec![0u8; metadata_size as usize];
     reader.read_exact(&mut metadata_bytes).await?;
     let metadata: CrateMetadata = serde_json::from_slice(&metadata_bytes)?;
 
     // extract crate tarball bytes from the request body
     let crate_size = reader.read_u32_le().await?;
+    let limits = &state.tarball_limits;
+    if u64::from(crate_size) > limits.max_size {
+        return Err(TarballError::TooLarge {
+            limit: limits.max_size,
+        }
+        .into());
+    }
     let mut crate_bytes = vec![0u8; crate_size as usize];
     reader.read_exact(&mut crate_bytes).await?;
 
@@ -49,11 +68,26 @@ pub async fn handler(
         metadata.version
     );
 
+    // check the crate name and the index white- and blacklist
+    validate_crate_name(&metadata.name)?;
+    if !token.allows_crate(&metadata.name) {
+        return Err(ApiError2::CrateNotInTokenScope(metadata.name.clone()));
+    }
+    state.filter.check(&metadata.name, &metadata.version)?;
+
+    // check that the tarball contains what the metadata claims,
+    // unpacking it is too expensive to block the async runtime with
+    let crate_bytes = {
+        let (name, version, limits) = (metadata.name.clone(), metadata.version.clone(), *limits);
+        tokio::task::spawn_blocking(move || {
+            tarball::verify(&crate_bytes, &name, &version, &limits).map(|_| crate_bytes)
+        })
+        .await
+        .map_err(std::io::Error::from)??
+    };
+
     // get the id of the crate (Some(_) if it already exists, otherwise None)
-    let crate_id: Option<i64> = sqlx::query_scalar("SELECT id FROM crates WHERE name = $1")
-        .bind(&metadata.name)
-        .fetch_optional(&state.db.pool)
-        .await?;
+    let crate_id = find_existing_crate(&state, &metadata.name).await?;
 
     match crate_id {
         Some(id) if token.scope.contains(Scope::PUBLISH_UPDATE) => {
@@ -79,6 +113,21 @@ pub async fn handler(
     Ok(Json(PublishResponse {}))
 }
 
+/// Looks up the id of the crate a version is published for.
+///
+/// Every spelling of a name refers to the same crate, but only the spelling
+/// the crate was first published with may be used to publish it.
+async fn find_existing_crate(state: &AppState, name: &str) -> Result<Option<i64>, ApiError2> {
+    match state.db.find_crate(name).await? {
+        Some((_, existing)) if existing != name => Err(ApiError2::CrateNameCollision {
+            name: name.to_string(),
+            existing,
+        }),
+        Some((id, _)) => Ok(Some(id)),
+        None => Ok(None),
+    }
+}
+
 /// Publish a new crate
 async fn publish_new(
     // The App-State
@@ -95,47 +144,14 @@ async fn publish_new(
         metadata.name,
         metadata.version
     );
-    // store crate on the disk
-    state
-        .storage
-        .store_crate(&metadata.name, &metadata.version, &crate_bytes)?;
-
-    // create index record for the new crate
-    let mut record: CrateVersion = metadata.clone().into(); // FIXME
-    record.checksum = sha256::digest(&crate_bytes);
-    state.index.add_record(record).unwrap(); // FIXME: remove unwrap
-
-    let mut tx = state.db.pool.begin().await?;
 
-    // create crate entry
-    let crate_id: i64 = sqlx::query_scalar(
-        "INSERT INTO crates (name, description, documentation, repository) VALUES ($1, $2, $3, $4) RETURNING id",
-    )
-    .bind(&metadata.name)
-    .bind(&metadata.description)
-    .bind(&metadata.documentation)
-    .bind(&metadata.repository)
-    .fetch_one(&mut *tx)
-    .await?;
-
-    // insert user as an owner for this new crate
-    let _ = sqlx::query("INSERT INTO crate_owners (user_id, crate_id) VALUES ($1, $2)")
-        .bind(&token.user_id)
-        .bind(&crate_id)
-        .execute(&mut *tx)
-        .await?;
-
-    // Insert a version entry for the crate (regardless if it is new or an update)
-    sqlx::query("INSERT INTO crate_versions (name, version, created_at) VALUES ($1, $2, $3)")
-        .bind(&metadata.name)
-        .bind(&metadata.version.to_string())
-        .bind(chrono::Utc::now().timestamp())
-        .execute(&mut *tx)
+    // reject reserved names and names confusable with other crates
+    let existing: Vec<String> = sqlx::query_scalar("SELECT name FROM crates")
+        .fetch_all(&state.db.pool)
         .await?;
+    state.name_policy.check(&metadata.name, &existing)?;
 
-    tx.commit().await?;
-
-    Ok(())
+    publish_version(state, token.user_id, None, metadata, &crate_bytes).await
 }
 
 /// Publish a new version of a crate
@@ -181,25 +197,564 @@ async fn publish_update(
         metadata.version
     );
 
-    // store crate on the disk
-    state
+    publish_version(state, token.user_id, Some(crate_id), metadata, &crate_bytes).await
+}
+
+/// Stores a crate version in the storage, the database and the index, all or nothing.
+///
+/// The tarball is staged first and the database rows are committed before the
+/// tarball is moved into place, so a failure in any step leaves no trace of the version.
+/// The index record is added last, as it makes the version visible to cargo;
+/// if that fails, the tarball and the database rows are removed again.
+/// A new crate (`crate_id` is `None`) is created with `user_id` as its owner,
+/// unless it is cached from the upstream registry in proxy mode.
+async fn publish_version(
+    state: &AppState,
+    user_id: i64,
+    crate_id: Option<i64>,
+    metadata: &CrateMetadata,
+    crate_bytes: &[u8],
+) -> Result<(), ApiError2> {
+    // in proxy mode, the index file of a crate that is not published locally is cached
+    // from upstream, and the records of a new local crate would be mixed into it
+    if crate_id.is_none()
+        && state.proxy.is_some()
+        && state.index.tree().read_record_file(&metadata.name).is_ok()
+    {
+        return Err(ApiError2::CrateExistsUpstream(metadata.name.clone()));
+    }
+
+    // render the README packaged with the crate, unpacking and rendering it
+    // is too expensive to block the async runtime with
+    let readme_html = {
+        let bytes = crate_bytes.to_vec();
+        let (name, version) = (metadata.name.clone(), metadata.version.clone());
+        let readme_path = readme::packaged_path(metadata.readme_file.as_deref());
+        let repository = metadata.repository.clone();
+        tokio::task::spawn_blocking(move || {
+            let max_size = readme::MAX_README_SIZE;
+            match tarball::read_file(&bytes, &name, &version, &readme_path, max_size) {
+                Ok(contents) => Ok(contents.map(|contents| {
+                    readme::render(&contents, &readme_path, repository.as_deref())
+                })),
+                // a README that is too large is not shown
+                Err(err @ TarballError::FileTooLarge { .. }) => {
+                    tracing::info!("not rendering the README of {} {}: {}", name, version, err);
+                    Ok(None)
+                }
+                Err(err) => Err(err),
+            }
+        })
+        .await
+        .map_err(std::io::Error::from)??
+    };
+
+    // stage the crate on the disk, it is removed again if it is never persisted
+    let staged = state
         .storage
-        .store_crate(&metadata.name, &metadata.version, &crate_bytes)?;
+        .stage_crate(&metadata.name, &metadata.version, crate_bytes)?;
 
-    // TODO: render + store readme on the disk
+    let mut tx = state.db.pool.begin().await?;
 
-    // create index record for the new crate
-    let mut record: CrateVersion = metadata.clone().into(); // FIXME
-    record.checksum = sha256::digest(&crate_bytes);
-    state.index.add_record(record).unwrap(); // FIXME: remove unwrap
+    if crate_id.is_none() {
+        // create crate entry, its metadata is taken from the version below
+        let crate_id: i64 =
+            sqlx::query_scalar("INSERT INTO crates (name) VALUES ($1) RETURNING id")
+                .bind(&metadata.name)
+                .fetch_one(&mut *tx)
+                .await?;
+
+        // insert user as an owner for this new crate
+        sqlx::query("INSERT INTO crate_owners (user_id, crate_id) VALUES ($1, $2)")
+            .bind(user_id)
+            .bind(crate_id)
+            .execute(&mut *tx)
+            .await?;
+    }
 
     // Insert a version entry for the crate (regardless if it is new or an update)
-    sqlx::query("INSERT INTO crate_versions (name, version, created_at) VALUES ($1, $2, $3)")
-        .bind(&metadata.name)
-        .bind(&metadata.version.to_string())
-        .bind(chrono::Utc::now().timestamp())
-        .execute(&state.db.pool)
-        .await?;
+    insert_version_row(&mut tx, metadata, readme_html.as_deref()).await?;
+    update_crate_metadata(&mut tx, &metadata.name).await?;
+
+    tx.commit().await?;
+
+    if let Err(err) = staged.persist() {
+        remove_version_rows(state, crate_id.is_none(), metadata).await;
+        return Err(err.into());
+    }
+
+    // create index record for the new crate
+    let mut record: CrateVersion = metadata.clone().into();
+    record.checksum = sha256::digest(crate_bytes);
+    if let Err(err) = state.index.add_record(record) {
+        if let Err(err) = state
+            .storage
+            .remove_crate(&metadata.name, &metadata.version)
+        {
+            tracing::error!(
+                "failed to remove crate '{}' ({}) after a failed publish: {}",
+                metadata.name,
+                metadata.version,
+                err
+            );
+        }
+        remove_version_rows(state, crate_id.is_none(), metadata).await;
+        return Err(err.into());
+    }
+
+    Ok(())
+}
 
+/// Inserts the version with its full metadata and rendered README.
+async fn insert_version_row(
+    conn: &mut SqliteConnection,
+    metadata: &CrateMetadata,
+    readme_html: Option<&str>,
+) -> Result<(), ApiError2> {
+    sqlx::query(
+        "INSERT INTO crate_versions (
+            name, version, created_at, description, homepage, documentation, repository,
+            license, license_file, readme, readme_file, links, rust_version,
+            authors, keywords, categories, badges, readme_html
+        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)",
+    )
+    .bind(&metadata.name)
+    .bind(metadata.version.to_string())
+    .bind(chrono::Utc::now().timestamp())
+    .bind(&metadata.description)
+    .bind(&metadata.homepage)
+    .bind(&metadata.documentation)
+    .bind(&metadata.repository)
+    .bind(&metadata.license)
+    .bind(&metadata.license_file)
+    .bind(&metadata.readme)
+    .bind(&metadata.readme_file)
+    .bind(&metadata.links)
+    .bind(&metadata.rust_version)
+    .bind(serde_json::to_string(&metadata.authors)?)
+    .bind(serde_json::to_string(
+        &metadata.keywords.clone().unwrap_or_default(),
+    )?)
+    .bind(serde_json::to_string(
+        &metadata.categories.clone().unwrap_or_default(),
+    )?)
+    .bind(serde_json::to_string(
+        &metadata.badges.clone().unwrap_or_default(),
+    )?)
+    .bind(readme_html)
+    .execute(conn)
+    .await?;
     Ok(())
 }
+
+/// Copies the metadata of the latest version of a crate to the crate itself.
+///
+/// Pre-releases are only used if the crate has no other versions.
+/// Versions published before their metadata was stored keep the previous values.
+async fn update_crate_metadata(conn: &mut SqliteConnection, name: &str) -> sqlx::Result<()> {
+    let versions: Vec<(i64, String)> =
+        sqlx::query_as("SELECT id, version FROM crate_versions WHERE name = $1")
+            .bind(name)
+            .fetch_all(&mut *conn)
+            .await?;
+    let Some((_, latest)) = versions
+        .into_iter()
+        .filter_map(|(id, version)| Some((Version::parse(&version).ok()?, id)))
+        .max_by_key(|(version, _)| (version.pre.is_empty(), version.clone()))
+    else {
+        return Ok(());
+    };
+
+    sqlx::query(
+        "UPDATE crates SET
+            description = coalesce(v.description, crates.description),
+            documentation = coalesce(v.documentation, crates.documentation),
+            repository = coalesce(v.repository, crates.repository),
+            homepage = coalesce(v.homepage, crates.homepage),
+            license = coalesce(v.license, crates.license),
+            keywords = v.keywords,
+            categories = v.categories
+        FROM (SELECT * FROM crate_versions WHERE id = $1) AS v
+        WHERE crates.name = $2",
+    )
+    .bind(latest)
+    .bind(name)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// Removes the database rows of a version whose publish failed after they were committed.
+async fn remove_version_rows(state: &AppState, is_new_crate: bool, metadata: &CrateMetadata) {
+    let result = async {
+        let mut tx = state.db.pool.begin().await?;
+        sqlx::query("DELETE FROM crate_versions WHERE name = $1 AND version = $2")
+            .bind(&metadata.name)
+            .bind(metadata.version.to_string())
+            .execute(&mut *tx)
+            .await?;
+        if is_new_crate {
+            sqlx::query(
+                "DELETE FROM crate_owners WHERE crate_id IN (SELECT id FROM crates WHERE name = $1)",
+            )
+            .bind(&metadata.name)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query("DELETE FROM crates WHERE name = $1")
+                .bind(&metadata.name)
+                .execute(&mut *tx)
+                .await?;
+        } else {
+            update_crate_metadata(&mut tx, &metadata.name).await?;
+        }
+        tx.commit().await
+    }
+    .await;
+
+    if let Err(err) = result {
+        tracing::error!(
+            "failed to remove crate '{}' ({}) from the database after a failed publish: {}",
+            metadata.name,
+            metadata.version,
+            err
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_existing_crate, publish_version};
+    use crate::api::error::ApiError2;
+    use crate::{
+        app::AppState,
+        crate_filter::CrateFilter,
+        proxy::{Proxy, ProxySettings},
+        tarball::tests::{crate_file, tarball},
+    };
+    use semver::Version;
+    use std::{fs, time::Duration};
+    use valhall_index::IndexTrait;
+    use valhall_models::crates::CrateMetadata;
+
+    const USER_ID: i64 = 1;
+
+    fn metadata(name: &str, version: &str) -> CrateMetadata {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "vers": version,
+            "deps": [],
+            "features": {},
+            "authors": [],
+        }))
+        .unwrap()
+    }
+
+    async fn app(dir: &std::path::Path) -> AppState {
+        let app = AppState::for_tests(dir).await;
+        sqlx::query("INSERT INTO users (id, email, name, password) VALUES ($1, 'a@b.c', 'a', '')")
+            .bind(USER_ID)
+            .execute(&app.db.pool)
+            .await
+            .unwrap();
+        app
+    }
+
+    async fn publish(app: &AppState, crate_id: Option<i64>, version: &str) -> bool {
+        let metadata = metadata("abcd", version);
+        let bytes = crate_file("abcd", &metadata.version);
+        publish_version(app, USER_ID, crate_id, &metadata, &bytes)
+            .await
+            .is_ok()
+    }
+
+    async fn count(app: &AppState, table: &str) -> i64 {
+        sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+            .fetch_one(&app.db.pool)
+            .await
+            .unwrap()
+    }
+
+    /// Asserts that no trace of `abcd` 1.0.0 is left anywhere.
+    async fn assert_not_published(app: &AppState, dir: &std::path::Path) {
+        assert_eq!(count(app, "crates").await, 0);
+        assert_eq!(count(app, "crate_owners").await, 0);
+        assert_eq!(count(app, "crate_versions").await, 0);
+        assert!(app.index.all_records("abcd").is_err());
+        assert!(app
+            .storage
+            .get_crate("abcd", Version::new(1, 0, 0))
+            .is_err());
+        // no staged file is left behind, whatever its temporary name
+        let storage_dir = dir.join("storage/abcd");
+        assert!(
+            std::fs::read_dir(&storage_dir).map_or(true, |mut entries| entries.next().is_none())
+        );
+    }
+
+    #[tokio::test]
+    async fn publish_succeeds() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(dir.path()).await;
+        assert!(publish(&app, None, "1.0.0").await);
+        assert!(publish(&app, Some(1), "1.1.0").await);
+
+        assert_eq!(count(&app, "crates").await, 1);
+        assert_eq!(count(&app, "crate_owners").await, 1);
+        assert_eq!(count(&app, "crate_versions").await, 2);
+        assert_eq!(app.index.all_records("abcd").unwrap().len(), 2);
+        let stored = app
+            .storage
+            .get_crate("abcd", Version::new(1, 1, 0))
+            .unwrap();
+        assert_eq!(
+            app.index.latest_record("abcd").unwrap().checksum,
+            sha256::digest(stored)
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_staging_publishes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(dir.path()).await;
+        // the crate directory of the storage cannot be created
+        fs::create_dir_all(dir.path().join("storage")).unwrap();
+        fs::write(dir.path().join("storage/abcd"), "").unwrap();
+
+        assert!(!publish(&app, None, "1.0.0").await);
+        assert_eq!(count(&app, "crate_versions").await, 0);
+        assert!(app.index.all_records("abcd").is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_database_insert_publishes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(dir.path()).await;
+        sqlx::query(
+            "CREATE TRIGGER fail BEFORE INSERT ON crate_versions BEGIN SELECT RAISE(ABORT, 'injected'); END",
+        )
+        .execute(&app.db.pool)
+        .await
+        .unwrap();
+
+        assert!(!publish(&app, None, "1.0.0").await);
+        assert_not_published(&app, dir.path()).await;
+    }
+
+    #[tokio::test]
+    async fn failed_storage_persist_publishes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(dir.path()).await;
+        // the staged file cannot be moved over a directory
+        fs::create_dir_all(dir.path().join("storage/abcd/abcd-1.0.0.crate")).unwrap();
+
+        assert!(!publish(&app, None, "1.0.0").await);
+        fs::remove_dir(dir.path().join("storage/abcd/abcd-1.0.0.crate")).unwrap();
+        assert_not_published(&app, dir.path()).await;
+    }
+
+    #[tokio::test]
+    async fn failed_index_write_publishes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(dir.path()).await;
+        // the index file of the crate cannot be read
+        fs::create_dir_all(dir.path().join("index/ab/cd/abcd")).unwrap();
+
+        assert!(!publish(&app, None, "1.0.0").await);
+        fs::remove_dir(dir.path().join("index/ab/cd/abcd")).unwrap();
+        assert_not_published(&app, dir.path()).await;
+    }
+
+    #[tokio::test]
+    async fn failed_update_keeps_the_crate() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(dir.path()).await;
+        assert!(publish(&app, None, "1.0.0").await);
+        let before = app.index.tree().read_record_file("abcd").unwrap().contents;
+
+        // the index directory of the crate cannot be written to
+        let index_dir = dir.path().join("index/ab/cd");
+        let temp_file = index_dir.join(".abcd.tmp");
+        fs::create_dir(&temp_file).unwrap();
+        assert!(!publish(&app, Some(1), "1.1.0").await);
+        fs::remove_dir(&temp_file).unwrap();
+
+        assert_eq!(count(&app, "crates").await, 1);
+        assert_eq!(count(&app, "crate_owners").await, 1);
+        assert_eq!(count(&app, "crate_versions").await, 1);
+        assert_eq!(
+            app.index.tree().read_record_file("abcd").unwrap().contents,
+            before
+        );
+        assert!(app
+            .storage
+            .get_crate("abcd", Version::new(1, 1, 0))
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn crate_shows_the_latest_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(dir.path()).await;
+        let publish = |version: &'static str, description: &'static str| {
+            let mut metadata = metadata("abcd", version);
+            metadata.description = Some(description.into());
+            metadata.keywords = Some(vec![description.into()]);
+            metadata.authors = vec!["A <a@b.c>".into()];
+            let bytes = crate_file("abcd", &metadata.version);
+            let crate_id = (version != "1.0.0").then_some(1);
+            let app = &app;
+            async move {
+                publish_version(app, USER_ID, crate_id, &metadata, &bytes)
+                    .await
+                    .is_ok()
+            }
+        };
+        let crate_metadata = || async {
+            sqlx::query_as::<_, (String, String)>("SELECT description, keywords FROM crates")
+                .fetch_one(&app.db.pool)
+                .await
+                .unwrap()
+        };
+
+        assert!(publish("1.0.0", "first").await);
+        assert!(publish("0.9.0", "older").await);
+        assert_eq!(
+            crate_metadata().await,
+            ("first".into(), r#"["first"]"#.into())
+        );
+        assert!(publish("1.1.0", "second").await);
+        assert_eq!(
+            crate_metadata().await,
+            ("second".into(), r#"["second"]"#.into())
+        );
+        assert!(publish("1.2.0-beta.1", "beta").await);
+        assert_eq!(
+            crate_metadata().await,
+            ("second".into(), r#"["second"]"#.into())
+        );
+
+        // a failed publish restores the metadata of the previous version
+        fs::create_dir(dir.path().join("index/ab/cd/.abcd.tmp")).unwrap();
+        assert!(!publish("2.0.0", "failed").await);
+        assert_eq!(
+            crate_metadata().await,
+            ("second".into(), r#"["second"]"#.into())
+        );
+
+        let (description, authors): (String, String) = sqlx::query_as(
+            "SELECT description, authors FROM crate_versions WHERE version = '0.9.0'",
+        )
+        .fetch_one(&app.db.pool)
+        .await
+        .unwrap();
+        assert_eq!(description, "older");
+        assert_eq!(authors, r#"["A <a@b.c>"]"#);
+    }
+
+    #[tokio::test]
+    async fn upstream_crates_cannot_be_published() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = app(dir.path()).await;
+        let settings = ProxySettings {
+            connect_timeout: Duration::from_secs(1),
+            timeout: Duration::from_secs(1),
+            refresh_interval: Duration::from_secs(60),
+        };
+        app.proxy = Some(Proxy::new(
+            "sparse+http://127.0.0.1:9/",
+            CrateFilter::default(),
+            settings,
+        ));
+        let cached =
+            r#"{"name":"abcd","vers":"0.1.0","deps":[],"cksum":"00","features":{},"yanked":false}"#;
+        app.index
+            .cache_record_file("abcd", cached.as_bytes())
+            .unwrap();
+
+        assert!(matches!(
+            publish_version(&app, USER_ID, None, &metadata("abcd", "1.0.0"), &[]).await,
+            Err(ApiError2::CrateExistsUpstream(_))
+        ));
+        assert_eq!(count(&app, "crates").await, 0);
+        assert_eq!(
+            app.index.tree().read_record_file("abcd").unwrap().contents,
+            cached.as_bytes()
+        );
+
+        // crates that are not cached can still be published
+        let metadata = metadata("efgh", "1.0.0");
+        let bytes = crate_file("efgh", &metadata.version);
+        publish_version(&app, USER_ID, None, &metadata, &bytes)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn readme_is_rendered() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(dir.path()).await;
+        let mut metadata = metadata("abcd", "1.0.0");
+        metadata.readme_file = Some("../README.md".into());
+        metadata.repository = Some("https://github.com/owner/abcd".into());
+        let manifest = "[package]\nname = \"abcd\"\nversion = \"1.0.0\"\n";
+        let bytes = tarball(&[
+            ("abcd-1.0.0/Cargo.toml", manifest),
+            ("abcd-1.0.0/README.md", "# abcd\n\n![logo](logo.png)"),
+        ]);
+        publish_version(&app, USER_ID, None, &metadata, &bytes)
+            .await
+            .unwrap();
+
+        let readme: String = sqlx::query_scalar("SELECT readme_html FROM crate_versions")
+            .fetch_one(&app.db.pool)
+            .await
+            .unwrap();
+        assert!(readme.contains("<h1>abcd</h1>"));
+        assert!(readme.contains("https://raw.githubusercontent.com/owner/abcd/HEAD/logo.png"));
+
+        // a README over the limit is not rendered, but does not fail the publish
+        metadata.version = Version::new(1, 1, 0);
+        let manifest = "[package]\nname = \"abcd\"\nversion = \"1.1.0\"\n";
+        let large = "a".repeat(crate::readme::MAX_README_SIZE as usize + 1);
+        let bytes = tarball(&[
+            ("abcd-1.1.0/Cargo.toml", manifest),
+            ("abcd-1.1.0/README.md", &large),
+        ]);
+        publish_version(&app, USER_ID, Some(1), &metadata, &bytes)
+            .await
+            .unwrap();
+        let readme: Option<String> =
+            sqlx::query_scalar("SELECT readme_html FROM crate_versions WHERE version = '1.1.0'")
+                .fetch_one(&app.db.pool)
+                .await
+                .unwrap();
+        assert_eq!(readme, None);
+    }
+
+    #[tokio::test]
+    async fn equivalent_names_collide() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app(dir.path()).await;
+        assert!(publish(&app, None, "1.0.0").await);
+
+        assert_eq!(find_existing_crate(&app, "abcd").await.unwrap(), Some(1));
+        assert_eq!(find_existing_crate(&app, "efgh").await.unwrap(), None);
+        for name in ["ABCD", "Abcd"] {
+            let err = find_existing_crate(&app, name).await.unwrap_err();
+            assert!(matches!(err, ApiError2::CrateNameCollision { .. }));
+        }
+        assert_eq!(
+            app.db.find_crate("ABCD").await.unwrap(),
+            Some((1, "abcd".to_string()))
+        );
+
+        // the database rejects colliding names as well
+        for (name, published) in [("Abcd", false), ("ab_cd", true), ("AB-CD", false)] {
+            let metadata = metadata(name, "1.0.0");
+            let bytes = crate_file(name, &metadata.version);
+            let result = publish_version(&app, USER_ID, None, &metadata, &bytes).await;
+            assert_eq!(result.is_ok(), published, "{}", name);
+        }
+        assert_eq!(count(&app, "crates").await, 2);
+    }
+}
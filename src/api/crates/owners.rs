@@ -1,4 +1,5 @@
 use crate::{
+    api::error::ApiError2,
     app::App,
     auth::{
         backend::{Scope, Token},
@@ -57,9 +58,9 @@ pub async fn get_handler(
     Path(name): Path<String>,
 ) -> Result<Json<OwnersListResponse>, ApiError> {
     tracing::trace!("Retrieving owners of crate '{}'", name);
-    let crate_id: i64 = sqlx::query_scalar("SELECT id FROM crates WHERE name = $1")
-        .bind(&name)
-        .fetch_optional(&app.db.pool)
+    let (crate_id, _) = app
+        .db
+        .find_crate(&name)
         .await?
         .ok_or(ApiError(anyhow!("Crate does not exist")))?;
 
@@ -88,13 +89,16 @@ pub async fn put_handler(
             "your api token does not contain the change-owners scope!"
         )));
     }
+    if !token.allows_crate(&name) {
+        return Err(ApiError2::CrateNotInTokenScope(name.clone()).into());
+    }
 
     tracing::trace!("Adding owners to crate '{}': {:?}", name, body.users);
 
     // get id of the current crate
-    let crate_id: i64 = sqlx::query_scalar("SELECT id FROM crates WHERE name = $1")
-        .bind(&name)
-        .fetch_optional(&app.db.pool)
+    let (crate_id, _) = app
+        .db
+        .find_crate(&name)
         .await?
         .ok_or(ApiError(anyhow!("crate does not exist")))?;
 
@@ -154,13 +158,16 @@ pub async fn delete_handler(
             "your api token does not contain the change-owners scope!"
         )));
     }
+    if !token.allows_crate(&name) {
+        return Err(ApiError2::CrateNotInTokenScope(name.clone()).into());
+    }
 
     tracing::trace!("Removing owners from crate '{}': {:?}", name, body.users);
 
     // get id of the current crate
-    let crate_id: i64 = sqlx::query_scalar("SELECT id FROM crates WHERE name = $1")
-        .bind(&name)
-        .fetch_optional(&app.db.pool)
+    let (crate_id, _) = app
+        .db
+        .find_crate(&name)
         .await?
         .ok_or(ApiError(anyhow!("crate does not exist")))?;
 
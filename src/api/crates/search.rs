@@ -1 +1,65 @@
-pub async fn handler() {}
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::error::ApiError2,
+    app::App,
+    search::{self, SearchHit, SearchOptions},
+};
+
+/// The maximum number of crates returned at once.
+const MAX_PER_PAGE: u32 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    #[serde(default)]
+    q: String,
+    #[serde(default = "default_per_page")]
+    per_page: u32,
+    #[serde(default = "default_page")]
+    page: u32,
+}
+
+fn default_per_page() -> u32 {
+    10
+}
+
+fn default_page() -> u32 {
+    1
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResponse {
+    crates: Vec<SearchHit>,
+    meta: SearchMeta,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchMeta {
+    total: i64,
+}
+
+/// Searches the crates of the registry, this endpoint is used by `cargo search`.
+pub async fn handler(
+    State(app): State<App>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<SearchResponse>, ApiError2> {
+    tracing::debug!("Searching crates for '{}'", query.q);
+    let options = SearchOptions {
+        query: query.q,
+        page: query.page,
+        per_page: query.per_page.clamp(1, MAX_PER_PAGE),
+        ..Default::default()
+    };
+    let results = search::search(&app.db, &options).await?;
+
+    Ok(Json(SearchResponse {
+        crates: results.hits,
+        meta: SearchMeta {
+            total: results.total,
+        },
+    }))
+}
@@ -11,7 +11,7 @@ use semver::Version;
 use serde::Serialize;
 use valhall_index::IndexTrait;
 
-use crate::{app::App, error::ApiError};
+use crate::{api::error::ApiError2, app::App, error::ApiError};
 
 #[derive(Debug, Serialize)]
 pub struct UnyankResponse {
@@ -28,7 +28,21 @@ pub async fn handler(
             "your api token does not contain the yank scope!"
         )));
     }
+    if !token.allows_crate(&name) {
+        return Err(ApiError2::CrateNotInTokenScope(name.clone()).into());
+    }
+    let (_, name) = app
+        .db
+        .find_crate(&name)
+        .await?
+        .ok_or(ApiError(anyhow!("crate does not exist!")))?;
     tracing::info!("Unyanking crate '{} ({})'", name, version);
-    app.index.unyank_record(&name, Version::parse(&version)?)?;
+    let version = Version::parse(&version)?;
+    app.index.unyank_record(&name, version.clone())?;
+    sqlx::query("UPDATE crate_versions SET yanked = false WHERE name = ? AND version = ?")
+        .bind(&name)
+        .bind(version.to_string())
+        .execute(&app.db.pool)
+        .await?;
     Ok(Json(UnyankResponse { ok: true }))
 }
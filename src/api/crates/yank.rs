@@ -11,7 +11,7 @@ use semver::Version;
 use serde::Serialize;
 use valhall_index::IndexTrait;
 
-use crate::{app::App, error::ApiError};
+use crate::{api::error::ApiError2, app::App, error::ApiError};
 
 #[derive(Serialize)]
 pub struct YankReponse {
@@ -30,10 +30,13 @@ pub async fn handler(
             "your api token does not contain the yank scope!"
         )));
     }
+    if !token.allows_crate(&name) {
+        return Err(ApiError2::CrateNotInTokenScope(name.clone()).into());
+    }
 
-    let crate_id: i64 = sqlx::query_scalar("SELECT id FROM crates WHERE name = ?")
-        .bind(&name)
-        .fetch_optional(&app.db.pool)
+    let (crate_id, name) = app
+        .db
+        .find_crate(&name)
         .await?
         .ok_or(ApiError(anyhow!("crate does not exist!")))?;
 
@@ -56,7 +59,12 @@ pub async fn handler(
 
     // yank the version of this crate on the index
     tracing::info!("Yanking crate '{} ({})'", name, version);
-    app.index.yank_record(&name, version)?;
+    app.index.yank_record(&name, version.clone())?;
+    sqlx::query("UPDATE crate_versions SET yanked = true WHERE name = ? AND version = ?")
+        .bind(&name)
+        .bind(version.to_string())
+        .execute(&app.db.pool)
+        .await?;
 
     Ok(Json(YankReponse { ok: true }))
 }
@@ -1,9 +1,18 @@
-use crate::auth::backend::Scope;
+use crate::{
+    auth::{
+        account::AccountError,
+        backend::{Scope, TokenError},
+    },
+    crate_filter::FilterError,
+    name_policy::NamePolicyError,
+    tarball::TarballError,
+};
 use askama_axum::Response;
 use axum::response::IntoResponse;
 use axum::Json;
 use semver::Version;
 use thiserror::Error;
+use valhall_models::name::NameError;
 
 #[derive(Debug, Error)]
 pub enum ApiError2 {
@@ -15,6 +24,10 @@ pub enum ApiError2 {
     #[error("Encountered an internal IO error")]
     IoError(#[from] std::io::Error),
 
+    /// Index error
+    #[error("Encountered an internal index error")]
+    IndexError(#[from] valhall_index::error::Error),
+
     /// Serde error
     #[error("Encountered an internal error 1: {0}")]
     SerdeError(#[from] serde_json::Error),
@@ -27,9 +40,57 @@ pub enum ApiError2 {
     #[error("The api token does not contain the `{0}` scope")]
     MissingTokenScope(Scope),
 
+    /// The token is limited to crates not matching the crate name
+    #[error("The api token may not be used for the crate `{0}`")]
+    CrateNotInTokenScope(String),
+
+    /// The crate version is rejected by the index white- or blacklist
+    #[error(transparent)]
+    CrateRejected(#[from] FilterError),
+
+    /// The uploaded `.crate` file is invalid or too large
+    #[error(transparent)]
+    InvalidCrate(#[from] TarballError),
+
+    /// The crate name does not follow the naming rules
+    #[error(transparent)]
+    InvalidCrateName(#[from] NameError),
+
+    /// The name of a new crate is reserved or confusable with another crate
+    #[error(transparent)]
+    NameRejected(#[from] NamePolicyError),
+
+    /// The crate name only differs in case or `-`/`_` from an existing crate
+    #[error("The crate name `{name}` is too similar to the existing crate `{existing}`")]
+    CrateNameCollision { name: String, existing: String },
+
+    /// The metadata of an upload is larger than the limit
+    #[error("The crate metadata is larger than the limit of {0} bytes")]
+    MetadataTooLarge(u32),
+
+    /// The registration or login of an account failed
+    #[error(transparent)]
+    Account(#[from] AccountError),
+
+    /// The request requires a logged in user
+    #[error("You have to be logged in")]
+    NotLoggedIn,
+
+    /// An api token could not be created or revoked
+    #[error(transparent)]
+    Token(#[from] TokenError),
+
     #[error("You are not an owner of this crate!")]
     CrateNotOwned,
 
+    /// A new crate would be mixed into the index file cached from the upstream registry
+    #[error("The crate `{0}` exists in the upstream registry")]
+    CrateExistsUpstream(String),
+
+    /// No crate with this name exists in the registry
+    #[error("The crate `{0}` does not exist")]
+    CrateNotFound(String),
+
     // It is not allowed to upload a crate with a version that
     // is already published in this registry
     #[error("This version ({0}) already exists!")]
@@ -0,0 +1,222 @@
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use std::io::Read;
+use valhall_index::{git::RecordFilter, Index};
+
+use crate::app::App;
+
+/// The maximum size of a (decompressed) negotiation request.
+const MAX_REQUEST_SIZE: u64 = 10 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct InfoRefsQuery {
+    service: Option<String>,
+}
+
+/// Advertises the refs of the index repository (`GET /git/index/info/refs`).
+pub async fn info_refs_handler(
+    State(app): State<App>,
+    Query(query): Query<InfoRefsQuery>,
+) -> Response {
+    if !matches!(app.index, Index::Git(_)) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    match query.service.as_deref() {
+        Some("git-upload-pack") => {}
+        // the index is only ever modified by the registry itself
+        Some("git-receive-pack") => return StatusCode::FORBIDDEN.into_response(),
+        // the dumb protocol is not supported
+        _ => return StatusCode::NOT_FOUND.into_response(),
+    }
+
+    // rejected records are filtered from the served history, the index itself keeps them
+    let refs = tokio::task::spawn_blocking(move || {
+        let Index::Git(index) = &app.index else {
+            unreachable!("checked above");
+        };
+        let filter = |contents: &[u8]| app.filter.filter_records(contents);
+        index.advertise_refs(app.filter.is_active().then_some(&filter as RecordFilter))
+    })
+    .await
+    .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()));
+    match refs {
+        Ok(refs) => git_response("application/x-git-upload-pack-advertisement", refs),
+        Err(err) => {
+            tracing::error!("failed to advertise the index refs: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Sends the objects a git client asked for (`POST /git/index/git-upload-pack`).
+pub async fn upload_pack_handler(
+    State(app): State<App>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    if !matches!(app.index, Index::Git(_)) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    // git compresses larger negotiation requests
+    let is_gzip = headers
+        .get(header::CONTENT_ENCODING)
+        .is_some_and(|encoding| encoding == "gzip");
+    let body = if is_gzip {
+        let mut decoded = Vec::new();
+        let mut decoder = GzDecoder::new(&body[..]).take(MAX_REQUEST_SIZE + 1);
+        if decoder.read_to_end(&mut decoded).is_err() {
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+        if decoded.len() as u64 > MAX_REQUEST_SIZE {
+            return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+        }
+        decoded
+    } else {
+        body.to_vec()
+    };
+
+    // packing the objects is blocking work of git2
+    let pack = tokio::task::spawn_blocking(move || {
+        let Index::Git(index) = &app.index else {
+            unreachable!("checked above");
+        };
+        let filter = |contents: &[u8]| app.filter.filter_records(contents);
+        index.upload_pack(
+            &body,
+            app.filter.is_active().then_some(&filter as RecordFilter),
+        )
+    })
+    .await
+    .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()));
+    match pack {
+        Ok(pack) => git_response("application/x-git-upload-pack-result", pack),
+        Err(err) => {
+            tracing::debug!("failed to answer upload-pack request: {}", err);
+            (StatusCode::BAD_REQUEST, err.to_string()).into_response()
+        }
+    }
+}
+
+fn git_response(content_type: &'static str, body: Vec<u8>) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MAX_REQUEST_SIZE;
+    use crate::{api::git_router, app::AppState};
+    use axum::{http::StatusCode, Router};
+    use flate2::{write::GzEncoder, Compression};
+    use semver::Version;
+    use std::{io::Write, path::Path, process::Command, sync::Arc};
+    use tokio::net::TcpListener;
+    use valhall_index::{
+        git::{CommitAuthor, GitIndex},
+        Index, IndexTrait,
+    };
+    use valhall_models::crates::CrateVersion;
+
+    fn record(name: &str, version: &str) -> CrateVersion {
+        CrateVersion {
+            name: name.into(),
+            version: Version::parse(version).unwrap(),
+            dependencies: vec![],
+            checksum: "0".repeat(64),
+            features: Default::default(),
+            yanked: false,
+            links: None,
+            rust_version: None,
+            features2: Default::default(),
+            v: None,
+        }
+    }
+
+    /// Runs git with `args` in `dir`, panicking if it fails.
+    fn git(dir: &Path, args: &[&str]) {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .output()
+            .expect("git has to be installed");
+        assert!(
+            output.status.success(),
+            "git {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    #[tokio::test]
+    async fn clone_and_fetch() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = AppState::for_tests(dir.path()).await;
+        let author = CommitAuthor {
+            name: "Registry Bot".into(),
+            email: "bot@example.com".into(),
+        };
+        app.index = Index::Git(GitIndex::new(dir.path().join("git"), author).unwrap());
+        app.index.add_record(record("abcd", "1.0.0")).unwrap();
+        let app = Arc::new(app);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/git/index", listener.local_addr().unwrap());
+        let router = Router::new()
+            .nest("/git", git_router())
+            .with_state(app.clone());
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        let clone = dir.path().join("clone");
+        let (clone_url, clone_dir) = (url.clone(), clone.clone());
+        tokio::task::spawn_blocking(move || {
+            git(
+                Path::new("."),
+                &["clone", "--quiet", &clone_url, clone_dir.to_str().unwrap()],
+            )
+        })
+        .await
+        .unwrap();
+        let contents = std::fs::read_to_string(clone.join("ab/cd/abcd")).unwrap();
+        assert!(contents.contains(r#""vers":"1.0.0""#));
+
+        // a fetch negotiates the commits the clone already has
+        app.index.add_record(record("abcd", "1.1.0")).unwrap();
+        app.index.add_record(record("efgh", "1.0.0")).unwrap();
+        let fetch_dir = clone.clone();
+        tokio::task::spawn_blocking(move || git(&fetch_dir, &["pull", "--quiet", "--ff-only"]))
+            .await
+            .unwrap();
+        let contents = std::fs::read_to_string(clone.join("ab/cd/abcd")).unwrap();
+        assert!(contents.contains(r#""vers":"1.1.0""#));
+        assert!(clone.join("ef/gh/efgh").exists());
+
+        // decompressed requests are limited
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder
+            .write_all(&vec![b'0'; MAX_REQUEST_SIZE as usize + 1])
+            .unwrap();
+        let response = reqwest::Client::new()
+            .post(format!("{}/git-upload-pack", url))
+            .header("content-encoding", "gzip")
+            .body(encoder.finish().unwrap())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -1,21 +1,25 @@
-use anyhow::anyhow;
-use axum::extract::Path;
+use axum::{
+    extract::{Path, State},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    Json,
+};
 
-use crate::error::ApiError;
+use crate::app::App;
 
-pub async fn handler(Path(path): Path<String>) -> Result<&'static str, ApiError> {
+pub(crate) mod git;
+pub(crate) mod sparse;
+
+pub async fn handler(
+    State(app): State<App>,
+    Path(path): Path<String>,
+    headers: HeaderMap,
+) -> Response {
     tracing::info!("index: {}", path);
 
     if path == "config.json" {
-        Ok(r#"{
-  "dl": "http://192.168.188.32:3000/api/v1/crates/{crate}/{version}/download",
-  "api": "http://192.168.188.32:3000",
-  "allowed-registries": [
-    "https://github.com/rust-lang/crates.io-index",
-    "sparse+http://localhost:3000/api/v1/index/"
-  ]
-}"#)
+        Json(app.registry_config.clone()).into_response()
     } else {
-        Err(ApiError(anyhow!("not implemented!")))
+        sparse::serve_record(&app, &path, &headers).await
     }
 }
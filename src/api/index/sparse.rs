@@ -0,0 +1,179 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use std::time::SystemTime;
+use valhall_index::{
+    error::{Error, IndexError},
+    tree::Tree,
+};
+
+use crate::{app::App, proxy::Proxy};
+
+/// The format of HTTP dates (RFC 7231, IMF-fixdate).
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Serves the index file at `path` (e.g. `3/s/syn` or `se/rd/serde`) using the sparse protocol.
+pub async fn serve_record(app: &App, path: &str, headers: &HeaderMap) -> Response {
+    let Some(name) = Tree::crate_name_from_path(path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    if let Some(proxy) = &app.proxy {
+        if proxy.claim_refresh(name) && !is_published_locally(app, name).await {
+            if app.index.tree().read_record_file(name).is_ok() {
+                // the cached records are served right away and refreshed for later reads
+                let app = app.clone();
+                let name = name.to_string();
+                tokio::spawn(async move {
+                    if let Some(proxy) = &app.proxy {
+                        refresh_record_file(proxy, &app, &name).await;
+                    }
+                });
+            } else {
+                refresh_record_file(proxy, app, name).await;
+            }
+        }
+    }
+
+    let file = match app.index.tree().read_record_file(name) {
+        Ok(file) => file,
+        Err(Error::IndexError(IndexError::CrateNotFound { .. })) => {
+            return StatusCode::NOT_FOUND.into_response()
+        }
+        Err(err) => {
+            tracing::error!("failed to read index file of crate '{}': {}", name, err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    // rejected versions are hidden from clients
+    let contents = app.filter.filter_records(&file.contents);
+    if contents.is_empty() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let etag = format!("\"{}\"", sha256::digest(&contents));
+    let last_modified = http_date(file.modified);
+
+    let mut response = if is_not_modified(headers, &etag, file.modified) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        let mut response = Response::new(Body::from(contents));
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; charset=utf-8"),
+        );
+        response
+    };
+
+    let response_headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(&etag) {
+        response_headers.insert(header::ETAG, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(&last_modified) {
+        response_headers.insert(header::LAST_MODIFIED, value);
+    }
+    response
+}
+
+async fn refresh_record_file(proxy: &Proxy, app: &App, name: &str) {
+    if let Err(err) = proxy.fetch_record_file(&app.index, name).await {
+        tracing::warn!("failed to fetch crate '{}' from upstream: {}", name, err);
+    }
+}
+
+async fn is_published_locally(app: &App, name: &str) -> bool {
+    app.db
+        .find_crate(name)
+        .await
+        // never overwrite local records if we cannot tell
+        .map_or(true, |id| id.is_some())
+}
+
+/// Checks the conditional request headers against the current state of an index file.
+///
+/// `If-None-Match` takes precedence over `If-Modified-Since` as mandated by RFC 7232.
+fn is_not_modified(headers: &HeaderMap, etag: &str, modified: SystemTime) -> bool {
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        return if_none_match.to_str().is_ok_and(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+        });
+    }
+
+    headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+        .is_some_and(|since| DateTime::<Utc>::from(modified).timestamp() <= since.timestamp())
+}
+
+/// Formats a timestamp as an HTTP date (e.g. `Sun, 06 Nov 1994 08:49:37 GMT`).
+pub(crate) fn http_date(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format(HTTP_DATE_FORMAT)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{http_date, is_not_modified};
+    use axum::http::{header, HeaderMap, HeaderValue};
+    use std::time::{Duration, SystemTime};
+
+    const ETAG: &str = "\"abc\"";
+
+    fn modified() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(784111777)
+    }
+
+    fn headers(name: header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn formats_http_date() {
+        assert_eq!(http_date(modified()), "Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+
+    #[test]
+    fn if_none_match() {
+        assert!(is_not_modified(
+            &headers(header::IF_NONE_MATCH, ETAG),
+            ETAG,
+            modified()
+        ));
+        assert!(is_not_modified(
+            &headers(header::IF_NONE_MATCH, "\"xyz\", W/\"abc\""),
+            ETAG,
+            modified()
+        ));
+        assert!(!is_not_modified(
+            &headers(header::IF_NONE_MATCH, "\"xyz\""),
+            ETAG,
+            modified()
+        ));
+        assert!(!is_not_modified(&HeaderMap::new(), ETAG, modified()));
+    }
+
+    #[test]
+    fn if_modified_since() {
+        assert!(is_not_modified(
+            &headers(header::IF_MODIFIED_SINCE, "Sun, 06 Nov 1994 08:49:37 GMT"),
+            ETAG,
+            modified()
+        ));
+        assert!(!is_not_modified(
+            &headers(header::IF_MODIFIED_SINCE, "Sun, 06 Nov 1994 08:49:36 GMT"),
+            ETAG,
+            modified()
+        ));
+    }
+}
@@ -1,24 +1,42 @@
 use axum::{
+    extract::DefaultBodyLimit,
     routing::{delete, get, post, put},
     Router,
 };
+use valhall_config::StorageConfig;
 
 use crate::app::App;
 
 mod account;
 mod crates;
 mod error;
-mod index;
+pub(crate) mod index;
 
 /// creates the router for all api endpoints
-pub fn router() -> Router<App> {
+pub fn router(storage: &StorageConfig) -> Router<App> {
+    // the upload consists of the metadata and the crate with their lengths
+    let max_upload_size =
+        storage.max_crate_size as usize + crates::new::MAX_METADATA_SIZE as usize + 8;
+
     Router::new()
         // account api
         .route("/account/login", post(account::login::handler))
+        .route("/account/logout", post(account::login::logout_handler))
         .route("/account/register", post(account::register::handler))
+        .route(
+            "/account/tokens",
+            get(account::tokens::list_handler).post(account::tokens::create_handler),
+        )
+        .route(
+            "/account/tokens/:id",
+            delete(account::tokens::delete_handler),
+        )
         // crates api
         .route("/crates", get(crates::search::handler))
-        .route("/crates/new", put(crates::new::handler))
+        .route(
+            "/crates/new",
+            put(crates::new::handler).layer(DefaultBodyLimit::max(max_upload_size)),
+        )
         // .route("/crates/suggest", get(handler))
         .route("/crates/:name", get(crates::info::handler))
         .route(
@@ -37,7 +55,17 @@ pub fn router() -> Router<App> {
             get(crates::download::handler),
         )
         // index api
-        .route("/index/:path", get(index::handler))
+        .route("/index/*path", get(index::handler))
     // .route("/index/git", get(index::git::handler))
     // .route("/index/sparse", get(index::sparse::handler))
 }
+
+/// creates the router for the git smart-HTTP endpoints of the index repository
+pub fn git_router() -> Router<App> {
+    Router::new()
+        .route("/index/info/refs", get(index::git::info_refs_handler))
+        .route(
+            "/index/git-upload-pack",
+            post(index::git::upload_pack_handler),
+        )
+}
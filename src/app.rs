@@ -1,7 +1,18 @@
-use crate::db::Database;
+use crate::{
+    crate_filter::CrateFilter,
+    db::Database,
+    name_policy::NamePolicy,
+    proxy::{Proxy, ProxySettings},
+    tarball::TarballLimits,
+};
 use std::sync::Arc;
 use valhall_config::Config;
-use valhall_index::{git::GitIndex, Index};
+use valhall_index::{
+    config::RegistryConfig,
+    git::{CommitAuthor, GitIndex},
+    sparse::SparseIndex,
+    Index,
+};
 use valhall_storage::Storage;
 
 pub type App = Arc<AppState>;
@@ -9,16 +20,96 @@ pub type App = Arc<AppState>;
 #[derive(Debug)]
 pub struct AppState {
     pub index: Index,
+    pub registry_config: RegistryConfig,
+    pub filter: CrateFilter,
+    /// The names new crates may not be published under.
+    pub name_policy: NamePolicy,
+    pub proxy: Option<Proxy>,
     pub storage: Storage,
+    /// The size limits of uploaded `.crate` files.
+    pub tarball_limits: TarballLimits,
+    /// Whether the session cookie is only sent over HTTPS, i.e. the public URL uses it.
+    pub secure_cookies: bool,
     pub db: Database,
 }
 
 impl AppState {
     pub async fn from_config(config: &Config) -> Self {
+        // the git index is also served over the sparse protocol,
+        // so it takes precedence if both flavours are enabled
+        let index = if config.index.git {
+            let author = CommitAuthor {
+                name: config.index.git_author.name.clone(),
+                email: config.index.git_author.email.clone(),
+            };
+            Index::Git(
+                GitIndex::new(config.index.path.clone(), author)
+                    .expect("failed to open the git index repository"),
+            )
+        } else {
+            Index::Sparse(SparseIndex::new(config.index.path.clone()))
+        };
+
+        // both index flavours have to advertise the same endpoints
+        let registry_config = RegistryConfig::new(
+            &config.server.public_url(),
+            config.index.allowed_registries.clone(),
+            config.index.auth_required,
+        );
+        index
+            .write_config(&registry_config)
+            .expect("failed to write the index config.json");
+
+        // index files are looked up at lowercase paths, older versions kept the case of the name
+        let moved = index
+            .normalize_record_paths()
+            .expect("failed to move index files to lowercase paths");
+        if !moved.is_empty() {
+            tracing::info!(
+                "moved the index files of {} crates to lowercase paths",
+                moved.len()
+            );
+        }
+
+        let filter = CrateFilter::from_config(&config.index);
+
         AppState {
-            index: Index::Git(GitIndex::new(config.index.path.clone())),
+            index,
+            registry_config,
+            proxy: config.index.crates_io_proxy.then(|| {
+                Proxy::new(
+                    &config.index.proxy_upstream,
+                    filter.clone(),
+                    ProxySettings::from_config(&config.index),
+                )
+            }),
+            filter,
+            name_policy: NamePolicy::from_config(&config.index.name_policy),
             storage: Storage::new(config.storage.path.clone()),
-            db: Database::init(&config).await.unwrap(),
+            tarball_limits: TarballLimits {
+                max_size: config.storage.max_crate_size,
+                max_unpacked_size: config.storage.max_unpacked_size,
+            },
+            secure_cookies: config.server.public_url().starts_with("https://"),
+            db: Database::init(config)
+                .await
+                .unwrap_or_else(|err| panic!("failed to open the database: {}", err)),
+        }
+    }
+
+    /// Builds a state with a sparse index and storage in `dir` and an in-memory database.
+    #[cfg(test)]
+    pub async fn for_tests(dir: &std::path::Path) -> Self {
+        AppState {
+            index: Index::Sparse(SparseIndex::new(dir.join("index"))),
+            registry_config: RegistryConfig::new("http://localhost", vec![], false),
+            filter: CrateFilter::default(),
+            name_policy: NamePolicy::default(),
+            proxy: None,
+            storage: Storage::new(dir.join("storage")),
+            tarball_limits: TarballLimits::default(),
+            secure_cookies: false,
+            db: Database::in_memory().await.unwrap(),
         }
     }
 }
@@ -0,0 +1,170 @@
+use argon2::{
+    password_hash::{self, PasswordHash, PasswordHasher, SaltString},
+    Argon2, PasswordVerifier,
+};
+use rand::rngs::OsRng;
+use std::sync::OnceLock;
+use thiserror::Error;
+
+use crate::db::Database;
+
+/// The minimum number of characters of a password.
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+
+#[derive(Debug, Error)]
+pub enum AccountError {
+    #[error("`{0}` is not a valid email address")]
+    InvalidEmail(String),
+    #[error("The name may not be empty")]
+    EmptyName,
+    #[error("The password has to be at least {MIN_PASSWORD_LENGTH} characters long")]
+    PasswordTooShort,
+    #[error("An account with the email address `{0}` already exists")]
+    EmailTaken(String),
+    #[error("Invalid email address or password")]
+    InvalidCredentials,
+    #[error("Failed to hash the password")]
+    Hash(password_hash::Error),
+    #[error("An internal database error occurred")]
+    Database(#[from] sqlx::Error),
+}
+
+/// Hashes a password with Argon2id and a random salt, in the PHC string format.
+pub fn hash_password(password: &str) -> Result<String, AccountError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(AccountError::Hash)
+}
+
+/// Checks a password against a hash created by [`hash_password`].
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|hash| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    })
+}
+
+/// A hash verified against for unknown email addresses, so that they take as long
+/// to reject as wrong passwords and do not reveal which addresses have an account.
+fn dummy_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| hash_password("valhall dummy password").expect("hashing never fails"))
+}
+
+/// Runs the deliberately slow password hashing outside of the async runtime.
+async fn blocking<T: Send + 'static>(func: impl FnOnce() -> T + Send + 'static) -> T {
+    tokio::task::spawn_blocking(func)
+        .await
+        .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))
+}
+
+/// Creates a new user account and returns its id.
+pub async fn register(
+    db: &Database,
+    email: &str,
+    name: &str,
+    password: &str,
+) -> Result<i64, AccountError> {
+    let email = email.trim();
+    let name = name.trim();
+    match email.split_once('@') {
+        Some((local, domain)) if !local.is_empty() && !domain.is_empty() => {}
+        _ => return Err(AccountError::InvalidEmail(email.to_string())),
+    }
+    if name.is_empty() {
+        return Err(AccountError::EmptyName);
+    }
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(AccountError::PasswordTooShort);
+    }
+
+    let password = password.to_string();
+    let password = blocking(move || hash_password(&password)).await?;
+    sqlx::query_scalar("INSERT INTO users (email, name, password) VALUES ($1, $2, $3) RETURNING id")
+        .bind(email)
+        .bind(name)
+        .bind(password)
+        .fetch_one(&db.pool)
+        .await
+        .map_err(|err| match err {
+            sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+                AccountError::EmailTaken(email.to_string())
+            }
+            err => err.into(),
+        })
+}
+
+/// Checks the credentials of a user and returns the id of the account.
+pub async fn authenticate(db: &Database, email: &str, password: &str) -> Result<i64, AccountError> {
+    let user: Option<(i64, String)> =
+        sqlx::query_as("SELECT id, password FROM users WHERE email = $1")
+            .bind(email.trim())
+            .fetch_optional(&db.pool)
+            .await?;
+
+    let (id, hash) = match user {
+        Some((id, hash)) => (Some(id), hash),
+        None => (None, dummy_hash().to_string()),
+    };
+    let password = password.to_string();
+    let valid = blocking(move || verify_password(&password, &hash)).await;
+
+    match id {
+        Some(id) if valid => Ok(id),
+        _ => Err(AccountError::InvalidCredentials),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{authenticate, hash_password, register, verify_password, AccountError};
+    use crate::db::Database;
+
+    #[test]
+    fn password_hash() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("wrong horse", &hash));
+        // the salt is random
+        assert_ne!(hash, hash_password("correct horse").unwrap());
+        assert!(!verify_password("correct horse", "plain text"));
+    }
+
+    #[tokio::test]
+    async fn register_and_authenticate() {
+        let db = Database::in_memory().await.unwrap();
+        let id = register(&db, " a@b.c ", "alice", "password1")
+            .await
+            .unwrap();
+        assert_eq!(authenticate(&db, "a@b.c", "password1").await.unwrap(), id);
+
+        assert!(matches!(
+            authenticate(&db, "a@b.c", "password2").await,
+            Err(AccountError::InvalidCredentials)
+        ));
+        assert!(matches!(
+            authenticate(&db, "b@b.c", "password1").await,
+            Err(AccountError::InvalidCredentials)
+        ));
+        assert!(matches!(
+            register(&db, "a@b.c", "bob", "password2").await,
+            Err(AccountError::EmailTaken(_))
+        ));
+        assert!(matches!(
+            register(&db, "b.c", "bob", "password2").await,
+            Err(AccountError::InvalidEmail(_))
+        ));
+        assert!(matches!(
+            register(&db, "b@b.c", " ", "password2").await,
+            Err(AccountError::EmptyName)
+        ));
+        assert!(matches!(
+            register(&db, "b@b.c", "bob", "short").await,
+            Err(AccountError::PasswordTooShort)
+        ));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::app::App;
+use crate::{app::App, auth::random_token, db::Database};
 use axum::{
     async_trait,
     extract::FromRequestParts,
@@ -6,15 +6,17 @@ use axum::{
 };
 use bitflags::bitflags;
 use sqlx::sqlite::SqliteRow;
-use sqlx::{FromRow, Row};
-use std::fmt::Display;
+use sqlx::{FromRow, Row, SqlitePool};
+use std::{fmt::Display, str::FromStr};
+use thiserror::Error;
+use valhall_models::name::canonical_crate_name;
 
 bitflags! {
     /// Bitflags for the different scope variants of a token.
     ///
     /// This is not an enum because we need to store it in
     /// the database as a single field and arrays are not supported
-    #[derive(Debug, PartialEq)]
+    #[derive(Debug, Clone, Copy, PartialEq)]
     pub struct Scope: u32 {
         const CHANGE_OWNERS  = 0b0001;
         const PUBLISH_NEW    = 0b0010;
@@ -40,21 +42,117 @@ impl Display for Scope {
     }
 }
 
+impl FromStr for Scope {
+    type Err = TokenError;
+
+    /// Parses the name of a single scope, as shown by its `Display` implementation.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "change-owners" => Ok(Self::CHANGE_OWNERS),
+            "publish-new" => Ok(Self::PUBLISH_NEW),
+            "publish-update" => Ok(Self::PUBLISH_UPDATE),
+            "yank" => Ok(Self::YANK),
+            _ => Err(TokenError::UnknownScope(s.to_string())),
+        }
+    }
+}
+
+impl Scope {
+    /// The names of the single scopes contained in this scope.
+    pub fn names(&self) -> Vec<String> {
+        self.iter().map(|scope| scope.to_string()).collect()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TokenError {
+    #[error("The token name may not be empty")]
+    EmptyName,
+    #[error("A token needs at least one scope")]
+    NoScope,
+    #[error("Unknown token scope `{0}`")]
+    UnknownScope(String),
+    #[error("`{0}` is not a valid crate scope, expected a crate name optionally ending with `*`")]
+    InvalidCratePattern(String),
+    #[error("The expiration date of a token has to be in the future")]
+    ExpiresInPast,
+    #[error("The token does not exist")]
+    NotFound,
+    #[error("An internal database error occurred")]
+    Database(#[from] sqlx::Error),
+}
+
+/// The prefix of the secrets of api tokens, making them recognizable e.g. for secret scanners.
+pub const TOKEN_PREFIX: &str = "vhl_";
+
+/// The digest under which the secret of a token is stored.
+pub fn hash_secret(secret: &str) -> String {
+    sha256::digest(secret)
+}
+
+/// Whether a crate scope is a crate name, optionally ending with `*`.
+fn is_valid_crate_pattern(pattern: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    let name = pattern.strip_suffix('*').unwrap_or(pattern);
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Replaces the secrets of tokens stored before tokens were hashed by their digests.
+pub async fn hash_plaintext_tokens(pool: &SqlitePool) -> sqlx::Result<()> {
+    let plaintext: Vec<(i64, String)> =
+        sqlx::query_as("SELECT id, token_hash FROM tokens WHERE plaintext = true")
+            .fetch_all(pool)
+            .await?;
+    if plaintext.is_empty() {
+        return Ok(());
+    }
+
+    tracing::info!("hashing {} plaintext api tokens", plaintext.len());
+    let mut tx = pool.begin().await?;
+    for (id, secret) in plaintext {
+        sqlx::query("UPDATE tokens SET token_hash = $1, plaintext = false WHERE id = $2")
+            .bind(hash_secret(&secret))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await
+}
+
 #[derive(Debug)]
 #[allow(unused)]
 pub(crate) struct Token {
-    id: u64,
-    token: String,
-    name: String,
+    pub(crate) id: i64,
+    token_hash: String,
+    pub(crate) name: String,
     pub(crate) scope: Scope,
     pub(crate) user_id: i64,
+    pub(crate) created_at: i64,
+    pub(crate) last_used_at: Option<i64>,
+    pub(crate) expires_at: Option<i64>,
+    /// The patterns of the crates the token may be used for, or `None` for all crates.
+    pub(crate) crate_scopes: Option<Vec<String>>,
+}
+
+/// The properties of a token to be created.
+#[derive(Debug)]
+pub struct NewToken {
+    pub name: String,
+    pub scope: Scope,
+    pub crate_scopes: Option<Vec<String>>,
+    pub expires_at: Option<i64>,
 }
 
 impl<'r> FromRow<'r, SqliteRow> for Token {
     fn from_row(row: &'r SqliteRow) -> Result<Self, sqlx::Error> {
         Ok(Token {
             id: row.try_get("id")?,
-            token: row.try_get("token")?,
+            token_hash: row.try_get("token_hash")?,
             name: row.try_get("name")?,
             scope: Scope::from_bits(row.try_get::<u32, _>("scope")?).ok_or(
                 sqlx::Error::ColumnDecode {
@@ -63,10 +161,125 @@ impl<'r> FromRow<'r, SqliteRow> for Token {
                 },
             )?,
             user_id: row.try_get("user_id")?,
+            created_at: row.try_get("created_at")?,
+            last_used_at: row.try_get("last_used_at")?,
+            expires_at: row.try_get("expires_at")?,
+            crate_scopes: row
+                .try_get::<Option<sqlx::types::Json<Vec<String>>>, _>("crate_scopes")?
+                .map(|patterns| patterns.0),
         })
     }
 }
 
+impl Token {
+    /// Creates a new token for a user and returns it together with its secret,
+    /// which is not shown again.
+    pub async fn create(
+        db: &Database,
+        user_id: i64,
+        new: NewToken,
+    ) -> Result<(Token, String), TokenError> {
+        let name = new.name.trim();
+        if name.is_empty() {
+            return Err(TokenError::EmptyName);
+        }
+        if new.scope.is_empty() {
+            return Err(TokenError::NoScope);
+        }
+        if let Some(pattern) = new
+            .crate_scopes
+            .iter()
+            .flatten()
+            .find(|pattern| !is_valid_crate_pattern(pattern))
+        {
+            return Err(TokenError::InvalidCratePattern(pattern.clone()));
+        }
+        let now = chrono::Utc::now().timestamp();
+        if new.expires_at.is_some_and(|expires_at| expires_at <= now) {
+            return Err(TokenError::ExpiresInPast);
+        }
+
+        // only the digest of the secret is stored
+        let secret = format!("{}{}", TOKEN_PREFIX, random_token());
+        let token = sqlx::query_as(
+            "INSERT INTO tokens (name, token_hash, scope, user_id, created_at, expires_at, crate_scopes)
+            VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING *",
+        )
+        .bind(name)
+        .bind(hash_secret(&secret))
+        .bind(new.scope.bits())
+        .bind(user_id)
+        .bind(now)
+        .bind(new.expires_at)
+        .bind(new.crate_scopes.map(sqlx::types::Json))
+        .fetch_one(&db.pool)
+        .await?;
+        Ok((token, secret))
+    }
+
+    /// Whether the token may be used for the crate `name`.
+    ///
+    /// A pattern ending with `*` matches all crates starting with the rest of it,
+    /// equivalent spellings of crate names are treated the same.
+    pub fn allows_crate(&self, name: &str) -> bool {
+        let Some(patterns) = &self.crate_scopes else {
+            return true;
+        };
+        let name = canonical_crate_name(name);
+        patterns.iter().any(|pattern| {
+            let pattern = canonical_crate_name(pattern);
+            match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == pattern,
+            }
+        })
+    }
+
+    /// Whether the token has expired.
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= chrono::Utc::now().timestamp())
+    }
+
+    /// Lists the tokens of a user, the newest first.
+    pub async fn list(db: &Database, user_id: i64) -> sqlx::Result<Vec<Token>> {
+        sqlx::query_as("SELECT * FROM tokens WHERE user_id = $1 ORDER BY created_at DESC, id DESC")
+            .bind(user_id)
+            .fetch_all(&db.pool)
+            .await
+    }
+
+    /// Finds the token of a secret and records that it was used.
+    /// Expired tokens are not found.
+    ///
+    /// The token is looked up by the SHA-256 digest of the secret. Timing differences of
+    /// the lookup can only reveal parts of a digest, which do not help guessing a secret.
+    pub async fn authenticate(db: &Database, secret: &str) -> sqlx::Result<Option<Token>> {
+        let digest = hash_secret(secret);
+        sqlx::query_as(
+            "UPDATE tokens SET last_used_at = $1
+            WHERE token_hash = $2 AND (expires_at IS NULL OR expires_at > $1) RETURNING *",
+        )
+        .bind(chrono::Utc::now().timestamp())
+        .bind(digest)
+        .fetch_optional(&db.pool)
+        .await
+    }
+
+    /// Revokes a token of a user.
+    pub async fn revoke(db: &Database, user_id: i64, id: i64) -> Result<(), TokenError> {
+        let result = sqlx::query("DELETE FROM tokens WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&db.pool)
+            .await?;
+        match result.rows_affected() {
+            0 => Err(TokenError::NotFound),
+            _ => Ok(()),
+        }
+    }
+}
+
 #[async_trait]
 impl FromRequestParts<App> for Token {
     type Rejection = StatusCode;
@@ -79,9 +292,7 @@ impl FromRequestParts<App> for Token {
             .to_str()
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-        let token: Option<Token> = sqlx::query_as("SELECT * FROM tokens WHERE token = $1")
-            .bind(auth)
-            .fetch_optional(&state.db.pool)
+        let token = Token::authenticate(&state.db, auth)
             .await
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
@@ -91,7 +302,10 @@ impl FromRequestParts<App> for Token {
 
 #[cfg(test)]
 mod tests {
-    use super::Scope;
+    use super::{
+        hash_plaintext_tokens, hash_secret, NewToken, Scope, Token, TokenError, TOKEN_PREFIX,
+    };
+    use crate::db::Database;
 
     #[test]
     fn scope_intersect() {
@@ -124,4 +338,176 @@ mod tests {
             Scope::from_bits(Scope::PUBLISH_NEW.bits() | Scope::PUBLISH_UPDATE.bits()).unwrap();
         assert_eq!(scope, Scope::PUBLISH);
     }
+
+    #[test]
+    fn scope_names() {
+        let scope = Scope::PUBLISH | Scope::YANK;
+        assert_eq!(scope.names(), vec!["publish-new", "publish-update", "yank"]);
+        for name in scope.names() {
+            assert!(scope.contains(name.parse().unwrap()));
+        }
+        assert!(matches!(
+            "publish".parse::<Scope>(),
+            Err(TokenError::UnknownScope(_))
+        ));
+    }
+
+    fn new_token(name: &str, scope: Scope) -> NewToken {
+        NewToken {
+            name: name.into(),
+            scope,
+            crate_scopes: None,
+            expires_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn manage_tokens() {
+        let db = Database::in_memory().await.unwrap();
+        sqlx::query(
+            "INSERT INTO users (id, email, name, password) VALUES (1, 'a@b.c', 'a', ''), (2, 'b@b.c', 'b', '')",
+        )
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        let (token, secret) = Token::create(&db, 1, new_token(" ci ", Scope::PUBLISH))
+            .await
+            .unwrap();
+        assert_eq!(token.name, "ci");
+        assert_eq!(token.scope, Scope::PUBLISH);
+        assert_eq!(token.last_used_at, None);
+        let (_, other) = Token::create(&db, 1, new_token("yank", Scope::YANK))
+            .await
+            .unwrap();
+        assert_ne!(secret, other);
+        assert!(secret.starts_with(TOKEN_PREFIX));
+
+        // the secret is only stored as a digest
+        let stored: String = sqlx::query_scalar("SELECT token_hash FROM tokens WHERE id = $1")
+            .bind(token.id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, hash_secret(&secret));
+        let used = Token::authenticate(&db, &secret).await.unwrap().unwrap();
+        assert_eq!(used.id, token.id);
+        assert!(used.last_used_at.is_some());
+        assert!(Token::authenticate(&db, &stored).await.unwrap().is_none());
+        assert!(matches!(
+            Token::create(&db, 1, new_token("", Scope::YANK)).await,
+            Err(TokenError::EmptyName)
+        ));
+        assert!(matches!(
+            Token::create(&db, 1, new_token("none", Scope::empty())).await,
+            Err(TokenError::NoScope)
+        ));
+
+        let names: Vec<String> = Token::list(&db, 1)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|token| token.name)
+            .collect();
+        assert_eq!(names, vec!["yank", "ci"]);
+        assert!(Token::list(&db, 2).await.unwrap().is_empty());
+
+        // only the owner of a token can revoke it
+        assert!(matches!(
+            Token::revoke(&db, 2, token.id).await,
+            Err(TokenError::NotFound)
+        ));
+        Token::revoke(&db, 1, token.id).await.unwrap();
+        assert_eq!(Token::list(&db, 1).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn hash_existing_tokens() {
+        let db = Database::in_memory().await.unwrap();
+        for statement in [
+            "INSERT INTO users (id, email, name, password) VALUES (1, 'a@b.c', 'a', '')",
+            "INSERT INTO tokens (name, token_hash, scope, user_id, plaintext) VALUES ('old', 'secret', 4, 1, true)",
+        ] {
+            sqlx::query(statement).execute(&db.pool).await.unwrap();
+        }
+
+        hash_plaintext_tokens(&db.pool).await.unwrap();
+        // hashing is only done once
+        hash_plaintext_tokens(&db.pool).await.unwrap();
+
+        let token = Token::authenticate(&db, "secret").await.unwrap().unwrap();
+        assert_eq!(token.name, "old");
+        assert!(Token::authenticate(&db, &hash_secret("secret"))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn restricted_tokens() {
+        let db = Database::in_memory().await.unwrap();
+        sqlx::query("INSERT INTO users (id, email, name, password) VALUES (1, 'a@b.c', 'a', '')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let (token, secret) = Token::create(
+            &db,
+            1,
+            NewToken {
+                crate_scopes: Some(vec!["my-team-*".into(), "serde".into()]),
+                expires_at: Some(chrono::Utc::now().timestamp() + 60),
+                ..new_token("ci", Scope::PUBLISH)
+            },
+        )
+        .await
+        .unwrap();
+        assert!(token.allows_crate("my-team-utils"));
+        assert!(token.allows_crate("My_Team_utils"));
+        assert!(token.allows_crate("serde"));
+        assert!(!token.allows_crate("serde-json"));
+        assert!(!token.allows_crate("other-team-utils"));
+        assert!(!token.is_expired());
+        assert!(Token::authenticate(&db, &secret).await.unwrap().is_some());
+
+        // expired tokens cannot be used anymore
+        sqlx::query("UPDATE tokens SET expires_at = 1")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert!(Token::authenticate(&db, &secret).await.unwrap().is_none());
+        assert!(Token::list(&db, 1).await.unwrap()[0].is_expired());
+
+        let (token, _) = Token::create(&db, 1, new_token("all", Scope::YANK))
+            .await
+            .unwrap();
+        assert!(token.allows_crate("anything"));
+
+        for pattern in ["my team", "*-utils", "-abc", ""] {
+            assert!(matches!(
+                Token::create(
+                    &db,
+                    1,
+                    NewToken {
+                        crate_scopes: Some(vec![pattern.into()]),
+                        ..new_token("invalid", Scope::YANK)
+                    }
+                )
+                .await,
+                Err(TokenError::InvalidCratePattern(_))
+            ));
+        }
+        assert!(matches!(
+            Token::create(
+                &db,
+                1,
+                NewToken {
+                    expires_at: Some(1),
+                    ..new_token("past", Scope::YANK)
+                }
+            )
+            .await,
+            Err(TokenError::ExpiresInPast)
+        ));
+    }
 }
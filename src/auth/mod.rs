@@ -1,53 +1,49 @@
+pub mod account;
 pub mod backend;
 pub mod frontend;
+pub mod session;
 
 use axum::{
     async_trait,
     extract::FromRequestParts,
-    http::{header, request::Parts, StatusCode},
+    http::{request::Parts, StatusCode},
     response::Redirect,
 };
+use axum_extra::extract::CookieJar;
+
+use rand::{rngs::OsRng, RngCore};
 
 use crate::app::App;
 
-pub struct RequireAuth;
+/// Generates a random secret of 32 bytes, hex encoded.
+pub(crate) fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Requires a valid session cookie, redirecting to the login page otherwise.
+pub struct RequireAuth {
+    pub(crate) user_id: i64,
+}
 
 #[async_trait]
 impl FromRequestParts<App> for RequireAuth {
     type Rejection = Redirect;
 
     async fn from_request_parts(parts: &mut Parts, state: &App) -> Result<Self, Self::Rejection> {
-        let session_token = parts
-            .headers
-            .get(header::AUTHORIZATION)
-            .and_then(|value| value.to_str().ok())
+        let jar = CookieJar::from_headers(&parts.headers);
+        let session_token = jar
+            .get(session::SESSION_COOKIE)
             .ok_or(Redirect::to("/account/login"))?;
 
-        let session_timestamp: i64 =
-            sqlx::query_scalar("SELECT expires FROM sessions WHERE token = ?")
-                .bind(session_token)
-                .fetch_optional(&state.db.pool)
-                .await
-                // some database error occured
-                .map_err(|_| Redirect::to("/account/login"))? // FIXME
-                // if the query returns None, the session token does
-                // not exist and we return early because the user is unauthorized
-                .ok_or(Redirect::to("/account/login"))?;
-
-        if session_timestamp <= chrono::Utc::now().timestamp() {
-            // the token the user used is
-            // remove the database entry for this session
-            sqlx::query("DELETE FROM sessions WHERE token = ?")
-                .bind(session_token)
-                .execute(&state.db.pool)
-                .await
-                .map_err(|_| Redirect::to("/account/login"))?; //FIXME
-
-            // return 401 unauthorized
-            return Err(Redirect::to("/account/login"));
-        }
+        // expired sessions do not exist anymore after the lookup
+        let user_id = session::user_id(&state.db, session_token.value())
+            .await
+            .map_err(|_| Redirect::to("/account/login"))? // FIXME
+            .ok_or(Redirect::to("/account/login"))?;
 
-        Ok(Self)
+        Ok(Self { user_id })
     }
 }
 
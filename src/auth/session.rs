@@ -0,0 +1,110 @@
+use axum_extra::extract::cookie::{Cookie, SameSite};
+
+use crate::{auth::random_token, db::Database};
+
+/// The name of the cookie holding the session token.
+pub const SESSION_COOKIE: &str = "valhall_session";
+
+/// How long a session stays valid after the login, in seconds.
+pub const SESSION_DURATION: i64 = 7 * 24 * 60 * 60;
+
+/// Starts a new session for a user and returns its token.
+pub async fn create(db: &Database, user_id: i64) -> sqlx::Result<String> {
+    let token = random_token();
+    sqlx::query("INSERT INTO sessions (token, user_id, expires) VALUES ($1, $2, $3)")
+        .bind(&token)
+        .bind(user_id)
+        .bind(chrono::Utc::now().timestamp() + SESSION_DURATION)
+        .execute(&db.pool)
+        .await?;
+    Ok(token)
+}
+
+/// Returns the user of a session, or `None` if the session does not exist or is expired.
+///
+/// Expired sessions are removed from the database.
+pub async fn user_id(db: &Database, token: &str) -> sqlx::Result<Option<i64>> {
+    let session: Option<(i64, i64)> =
+        sqlx::query_as("SELECT user_id, expires FROM sessions WHERE token = $1")
+            .bind(token)
+            .fetch_optional(&db.pool)
+            .await?;
+
+    match session {
+        Some((user_id, expires)) if expires > chrono::Utc::now().timestamp() => Ok(Some(user_id)),
+        Some(_) => {
+            delete(db, token).await?;
+            Ok(None)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Ends a session.
+pub async fn delete(db: &Database, token: &str) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM sessions WHERE token = $1")
+        .bind(token)
+        .execute(&db.pool)
+        .await?;
+    Ok(())
+}
+
+/// The cookie storing the session token in the browser.
+///
+/// It is not readable by scripts. `secure` restricts it to HTTPS, which browsers
+/// would otherwise refuse to store for a registry served over plain HTTP.
+pub fn cookie(token: String, secure: bool) -> Cookie<'static> {
+    Cookie::build((SESSION_COOKIE, token))
+        .path("/")
+        .secure(secure)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .build()
+}
+
+/// A cookie matching [`cookie`], used to remove it from the browser.
+pub fn removal_cookie() -> Cookie<'static> {
+    Cookie::build(SESSION_COOKIE).path("/").build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cookie, create, delete, user_id};
+    use crate::db::Database;
+
+    #[tokio::test]
+    async fn session_lifecycle() {
+        let db = Database::in_memory().await.unwrap();
+        sqlx::query("INSERT INTO users (id, email, name, password) VALUES (1, 'a@b.c', 'a', '')")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let token = create(&db, 1).await.unwrap();
+        assert_eq!(token.len(), 64);
+        assert_eq!(user_id(&db, &token).await.unwrap(), Some(1));
+        assert_eq!(user_id(&db, "unknown").await.unwrap(), None);
+
+        delete(&db, &token).await.unwrap();
+        assert_eq!(user_id(&db, &token).await.unwrap(), None);
+
+        // expired sessions are cleaned up
+        let token = create(&db, 1).await.unwrap();
+        sqlx::query("UPDATE sessions SET expires = 0")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(user_id(&db, &token).await.unwrap(), None);
+        let count: i64 = sqlx::query_scalar("SELECT count(*) FROM sessions")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn secure_cookie() {
+        assert_eq!(cookie("token".into(), true).secure(), Some(true));
+        assert_eq!(cookie("token".into(), false).secure(), Some(false));
+    }
+}
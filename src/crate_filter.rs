@@ -0,0 +1,175 @@
+use semver::Version;
+use serde::Deserialize;
+use thiserror::Error;
+use valhall_config::{CrateRule, IndexConfig};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum FilterError {
+    #[error("crate '{name}' ({version}) is not on the index whitelist")]
+    NotWhitelisted { name: String, version: Version },
+    #[error("crate '{name}' ({version}) is rejected by the index blacklist rule '{rule}'")]
+    Blacklisted {
+        name: String,
+        version: Version,
+        rule: String,
+    },
+}
+
+/// Decides which crate versions the registry accepts and serves,
+/// based on the white- and blacklist of the index config.
+#[derive(Debug, Clone, Default)]
+pub struct CrateFilter {
+    whitelist: Option<Vec<CrateRule>>,
+    blacklist: Option<Vec<CrateRule>>,
+}
+
+/// The part of an index record needed to filter it.
+#[derive(Deserialize)]
+struct RecordKey {
+    name: String,
+    vers: Version,
+}
+
+impl CrateFilter {
+    pub fn from_config(config: &IndexConfig) -> Self {
+        Self {
+            whitelist: config
+                .whitelist
+                .enabled
+                .then(|| config.whitelist.crates.clone()),
+            blacklist: config
+                .blacklist
+                .enabled
+                .then(|| config.blacklist.crates.clone()),
+        }
+    }
+
+    /// Whether the white- or blacklist is enabled.
+    pub fn is_active(&self) -> bool {
+        self.whitelist.is_some() || self.blacklist.is_some()
+    }
+
+    /// Checks whether a crate version may be published or served.
+    pub fn check(&self, name: &str, version: &Version) -> Result<(), FilterError> {
+        if let Some(whitelist) = &self.whitelist {
+            if !whitelist.iter().any(|rule| rule.matches(name, version)) {
+                return Err(FilterError::NotWhitelisted {
+                    name: name.to_string(),
+                    version: version.clone(),
+                });
+            }
+        }
+
+        if let Some(blacklist) = &self.blacklist {
+            if let Some(rule) = blacklist.iter().find(|rule| rule.matches(name, version)) {
+                return Err(FilterError::Blacklisted {
+                    name: name.to_string(),
+                    version: version.clone(),
+                    rule: rule.to_string(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes all records of rejected versions from the contents of an index file.
+    pub fn filter_records(&self, contents: &[u8]) -> Vec<u8> {
+        if !self.is_active() {
+            return contents.to_vec();
+        }
+
+        let mut filtered = Vec::with_capacity(contents.len());
+        for line in contents.split_inclusive(|byte| *byte == b'\n') {
+            let allowed = serde_json::from_slice::<RecordKey>(line)
+                .is_ok_and(|record| self.check(&record.name, &record.vers).is_ok());
+            if allowed {
+                filtered.extend_from_slice(line);
+            }
+        }
+        filtered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CrateFilter, FilterError};
+    use semver::Version;
+    use valhall_config::CrateRule;
+
+    fn rules(toml: &str) -> Option<Vec<CrateRule>> {
+        #[derive(serde::Deserialize)]
+        struct Rules {
+            crates: Vec<CrateRule>,
+        }
+        Some(toml::from_str::<Rules>(toml).unwrap().crates)
+    }
+
+    #[test]
+    fn whitelist() {
+        let filter = CrateFilter {
+            whitelist: rules(
+                r#"crates = [
+                    { name = "abc" },
+                    { name = "test", version = ["1.2", ">2.0"] },
+                    { name = "xyz", version = ">1.2, <=2.0" },
+                ]"#,
+            ),
+            blacklist: None,
+        };
+
+        assert!(filter.check("abc", &Version::new(0, 1, 0)).is_ok());
+        assert!(filter.check("test", &Version::new(1, 2, 5)).is_ok());
+        assert!(filter.check("test", &Version::new(2, 1, 0)).is_ok());
+        assert!(filter.check("xyz", &Version::new(2, 0, 0)).is_ok());
+        assert_eq!(
+            filter.check("test", &Version::new(1, 1, 0)),
+            Err(FilterError::NotWhitelisted {
+                name: "test".into(),
+                version: Version::new(1, 1, 0)
+            })
+        );
+        assert!(filter.check("xyz", &Version::new(1, 2, 0)).is_err());
+        assert!(filter.check("other", &Version::new(1, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn blacklist() {
+        let filter = CrateFilter {
+            whitelist: None,
+            blacklist: rules(r#"crates = [{ name = "foo_bar", version = ">=1.0" }]"#),
+        };
+
+        assert!(filter.check("foo-bar", &Version::new(0, 9, 0)).is_ok());
+        assert!(filter.check("other", &Version::new(1, 0, 0)).is_ok());
+        let err = filter.check("Foo-Bar", &Version::new(1, 0, 0)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "crate 'Foo-Bar' (1.0.0) is rejected by the index blacklist rule 'foo_bar (>=1.0)'"
+        );
+    }
+
+    #[test]
+    fn filter_records() {
+        let filter = CrateFilter {
+            whitelist: None,
+            blacklist: rules(r#"crates = [{ name = "abc", version = "^1" }]"#),
+        };
+        let contents = concat!(
+            r#"{"name":"abc","vers":"0.1.0","cksum":"00"}"#,
+            "\n",
+            r#"{"name":"abc","vers":"1.0.0","cksum":"00"}"#,
+            "\n",
+            r#"{"name":"abc","vers":"2.0.0","cksum":"00"}"#,
+            "\n",
+        );
+
+        let filtered = String::from_utf8(filter.filter_records(contents.as_bytes())).unwrap();
+        assert_eq!(filtered.lines().count(), 2);
+        assert!(!filtered.contains("1.0.0"));
+        assert_eq!(
+            CrateFilter::default().filter_records(contents.as_bytes()),
+            contents.as_bytes()
+        );
+    }
+}
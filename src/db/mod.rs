@@ -1,8 +1,12 @@
-use crate::error::Result;
+use crate::{
+    auth::backend::hash_plaintext_tokens,
+    error::{Error, Result},
+};
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
 use sqlx::SqlitePool;
 use std::str::FromStr;
 use valhall_config::Config;
+use valhall_models::name::canonical_crate_name;
 
 #[derive(Debug)]
 pub struct Database {
@@ -18,6 +22,32 @@ impl Database {
 
         let pool = SqlitePoolOptions::new().connect_with(options).await?;
 
+        check_canonical_names(&pool).await?;
+        sqlx::migrate!().run(&pool).await?;
+        hash_plaintext_tokens(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    /// Finds a crate by any equivalent spelling of its name.
+    ///
+    /// Returns the id and the actual name of the crate.
+    pub async fn find_crate(&self, name: &str) -> sqlx::Result<Option<(i64, String)>> {
+        sqlx::query_as("SELECT id, name FROM crates WHERE lower(replace(name, '_', '-')) = $1")
+            .bind(canonical_crate_name(name))
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    /// Opens a fresh, migrated in-memory database.
+    #[cfg(test)]
+    pub async fn in_memory() -> Result<Self> {
+        // every connection would get its own in-memory database
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await?;
+
         sqlx::migrate!().run(&pool).await?;
 
         Ok(Self { pool })
@@ -28,3 +58,74 @@ impl Database {
     //     Ok(t)
     // }
 }
+
+/// Fails with a list of the conflicting crates if the database holds crates whose names
+/// only differ in case or in `-` and `_`.
+///
+/// The migration adding the unique index on the canonical crate names would otherwise
+/// abort with a bare constraint violation. Databases which already have the index are skipped.
+async fn check_canonical_names(pool: &SqlitePool) -> Result<()> {
+    let pending: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'crates')
+            AND NOT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'index' AND name = 'crates_canonical_name')",
+    )
+    .fetch_one(pool)
+    .await?;
+    if !pending {
+        return Ok(());
+    }
+
+    let conflicts: Vec<String> = sqlx::query_scalar(
+        "SELECT group_concat(name, ', ') FROM crates
+            GROUP BY lower(replace(name, '_', '-')) HAVING count(*) > 1 ORDER BY 1",
+    )
+    .fetch_all(pool)
+    .await?;
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+
+    Err(Error::Other(format!(
+        "the database contains crates whose names only differ in case or in `-` and `_`, \
+         which refer to the same crate now: {}. Rename or delete all but one crate of each group \
+         (including their versions and owners) before starting this version of valhall.",
+        conflicts
+            .iter()
+            .map(|names| format!("[{}]", names))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_canonical_names, Database};
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    #[tokio::test]
+    async fn canonical_name_conflicts() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        // a fresh database has nothing to check
+        check_canonical_names(&pool).await.unwrap();
+
+        sqlx::query("CREATE TABLE crates (id INTEGER PRIMARY KEY, name TEXT NOT NULL UNIQUE)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO crates (name) VALUES ('foo_bar'), ('Foo-Bar'), ('serde')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let err = check_canonical_names(&pool).await.unwrap_err().to_string();
+        assert!(err.contains("foo_bar"), "{}", err);
+        assert!(err.contains("Foo-Bar"), "{}", err);
+        assert!(!err.contains("serde"), "{}", err);
+
+        let db = Database::in_memory().await.unwrap();
+        check_canonical_names(&db.pool).await.unwrap();
+    }
+}
@@ -1,30 +1,287 @@
 use askama::Template;
-use axum::response::Redirect;
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Redirect,
+    Form,
+};
+use axum_extra::extract::CookieJar;
+use chrono::{DateTime, NaiveDate, NaiveTime};
+use serde::Deserialize;
+
+use crate::{
+    app::App,
+    auth::{
+        account,
+        backend::{NewToken, Scope, Token, TokenError},
+        session, RequireAuth,
+    },
+};
 
 #[derive(Template)]
 #[template(path = "account/login.html")]
-pub(crate) struct LoginTemplate {}
+pub(crate) struct LoginTemplate {
+    email: String,
+    error: Option<String>,
+}
 
 #[derive(Template)]
 #[template(path = "account/register.html")]
-pub(crate) struct RegisterTemplate {}
+pub(crate) struct RegisterTemplate {
+    email: String,
+    name: String,
+    error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "account/profile.html")]
+pub(crate) struct ProfileTemplate {
+    email: String,
+    name: String,
+}
 
 #[derive(Template)]
-#[template(path = "account/register.html")] // fixme
-pub(crate) struct ProfileTemplate {}
+#[template(path = "account/tokens.html")]
+pub(crate) struct TokensTemplate {
+    tokens: Vec<TokenRow>,
+    /// The name and secret of a token that was just created.
+    created: Option<(String, String)>,
+    error: Option<String>,
+}
+
+pub(crate) struct TokenRow {
+    id: i64,
+    name: String,
+    scopes: String,
+    crates: String,
+    created_at: String,
+    last_used_at: String,
+    expires_at: String,
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    email: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+pub struct RegisterForm {
+    email: String,
+    name: String,
+    password: String,
+}
 
 pub async fn login_handler() -> LoginTemplate {
-    LoginTemplate {}
+    LoginTemplate {
+        email: String::new(),
+        error: None,
+    }
+}
+
+pub async fn login_post_handler(
+    State(app): State<App>,
+    jar: CookieJar,
+    Form(form): Form<LoginForm>,
+) -> Result<(CookieJar, Redirect), LoginTemplate> {
+    let login = async {
+        let user_id = account::authenticate(&app.db, &form.email, &form.password).await?;
+        Ok::<_, account::AccountError>(session::create(&app.db, user_id).await?)
+    };
+    match login.await {
+        Ok(token) => Ok((
+            jar.add(session::cookie(token, app.secure_cookies)),
+            Redirect::to("/account/profile"),
+        )),
+        Err(err) => Err(LoginTemplate {
+            email: form.email,
+            error: Some(err.to_string()),
+        }),
+    }
 }
 
 pub async fn register_handler() -> RegisterTemplate {
-    RegisterTemplate {}
+    RegisterTemplate {
+        email: String::new(),
+        name: String::new(),
+        error: None,
+    }
 }
 
-pub async fn profile_handler() -> Result<ProfileTemplate, Redirect> {
-    Err(Redirect::to("/account/login"))
+/// Creates the account and logs the new user in.
+pub async fn register_post_handler(
+    State(app): State<App>,
+    jar: CookieJar,
+    Form(form): Form<RegisterForm>,
+) -> Result<(CookieJar, Redirect), RegisterTemplate> {
+    let register = async {
+        let user_id = account::register(&app.db, &form.email, &form.name, &form.password).await?;
+        Ok::<_, account::AccountError>(session::create(&app.db, user_id).await?)
+    };
+    match register.await {
+        Ok(token) => Ok((
+            jar.add(session::cookie(token, app.secure_cookies)),
+            Redirect::to("/account/profile"),
+        )),
+        Err(err) => Err(RegisterTemplate {
+            email: form.email,
+            name: form.name,
+            error: Some(err.to_string()),
+        }),
+    }
 }
 
-pub async fn token_handler() -> Result<ProfileTemplate, Redirect> {
-    Err(Redirect::to("/account/login"))
+pub async fn logout_handler(
+    State(app): State<App>,
+    jar: CookieJar,
+) -> Result<(CookieJar, Redirect), StatusCode> {
+    if let Some(cookie) = jar.get(session::SESSION_COOKIE) {
+        session::delete(&app.db, cookie.value())
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+    Ok((jar.remove(session::removal_cookie()), Redirect::to("/")))
+}
+
+pub async fn profile_handler(
+    State(app): State<App>,
+    auth: RequireAuth,
+) -> Result<ProfileTemplate, StatusCode> {
+    let (email, name) = sqlx::query_as("SELECT email, name FROM users WHERE id = $1")
+        .bind(auth.user_id)
+        .fetch_one(&app.db.pool)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(ProfileTemplate { email, name })
+}
+
+/// The checked scopes of a new token.
+#[derive(Deserialize)]
+pub struct CreateTokenForm {
+    name: String,
+    /// Crate patterns separated by commas or whitespace, all crates if empty.
+    #[serde(default)]
+    crate_scopes: String,
+    /// The day the token expires on (`YYYY-MM-DD`), never if empty.
+    #[serde(default)]
+    expires: String,
+    change_owners: Option<String>,
+    publish_new: Option<String>,
+    publish_update: Option<String>,
+    yank: Option<String>,
+}
+
+pub async fn token_handler(
+    State(app): State<App>,
+    auth: RequireAuth,
+) -> Result<TokensTemplate, StatusCode> {
+    tokens_page(&app, auth.user_id, None, None).await
+}
+
+/// Creates a token and shows its secret, which is not shown again.
+pub async fn create_token_handler(
+    State(app): State<App>,
+    auth: RequireAuth,
+    Form(form): Form<CreateTokenForm>,
+) -> Result<TokensTemplate, StatusCode> {
+    let mut scope = Scope::empty();
+    for (checked, flag) in [
+        (&form.change_owners, Scope::CHANGE_OWNERS),
+        (&form.publish_new, Scope::PUBLISH_NEW),
+        (&form.publish_update, Scope::PUBLISH_UPDATE),
+        (&form.yank, Scope::YANK),
+    ] {
+        if checked.is_some() {
+            scope |= flag;
+        }
+    }
+
+    let crate_scopes: Vec<String> = form
+        .crate_scopes
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|pattern| !pattern.is_empty())
+        .map(String::from)
+        .collect();
+    let expires_at = match form.expires.trim() {
+        "" => None,
+        date => match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+            // the token stays valid for the whole chosen day
+            Ok(date) => Some(
+                date.and_time(NaiveTime::from_hms_opt(23, 59, 59).unwrap())
+                    .and_utc()
+                    .timestamp(),
+            ),
+            Err(_) => {
+                let error = format!("`{}` is not a valid date", date);
+                return tokens_page(&app, auth.user_id, None, Some(error)).await;
+            }
+        },
+    };
+    let new = NewToken {
+        name: form.name,
+        scope,
+        crate_scopes: (!crate_scopes.is_empty()).then_some(crate_scopes),
+        expires_at,
+    };
+
+    match Token::create(&app.db, auth.user_id, new).await {
+        Ok((token, secret)) => {
+            tokens_page(&app, auth.user_id, Some((token.name, secret)), None).await
+        }
+        Err(err) => tokens_page(&app, auth.user_id, None, Some(err.to_string())).await,
+    }
+}
+
+pub async fn revoke_token_handler(
+    State(app): State<App>,
+    auth: RequireAuth,
+    Path(id): Path<i64>,
+) -> Result<Redirect, StatusCode> {
+    // revoking a token that does not exist anymore is not an error here
+    match Token::revoke(&app.db, auth.user_id, id).await {
+        Ok(()) | Err(TokenError::NotFound) => Ok(Redirect::to("/account/token")),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+async fn tokens_page(
+    app: &App,
+    user_id: i64,
+    created: Option<(String, String)>,
+    error: Option<String>,
+) -> Result<TokensTemplate, StatusCode> {
+    let tokens = Token::list(&app.db, user_id)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let format = |seconds: i64| match DateTime::from_timestamp(seconds, 0) {
+        Some(time) => time.format("%Y-%m-%d %H:%M UTC").to_string(),
+        None => String::new(),
+    };
+    Ok(TokensTemplate {
+        tokens: tokens
+            .into_iter()
+            .map(|token| TokenRow {
+                id: token.id,
+                scopes: token.scope.names().join(", "),
+                crates: match &token.crate_scopes {
+                    Some(patterns) => patterns.join(", "),
+                    None => "all".into(),
+                },
+                expires_at: match token.expires_at {
+                    Some(_) if token.is_expired() => "expired".into(),
+                    Some(expires_at) => format(expires_at),
+                    None => "never".into(),
+                },
+                created_at: format(token.created_at),
+                last_used_at: token
+                    .last_used_at
+                    .map(format)
+                    .unwrap_or_else(|| "never".into()),
+                name: token.name,
+            })
+            .collect(),
+        created,
+        error,
+    })
 }
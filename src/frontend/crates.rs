@@ -4,6 +4,7 @@ use axum::extract::{Path, State};
 use axum::http::StatusCode;
 use semver::Version;
 use sqlx::FromRow;
+use valhall_models::name::canonical_crate_name;
 
 #[derive(Template)]
 #[template(path = "crates/index.html")]
@@ -16,7 +17,10 @@ pub(crate) struct IndexTemplate {
     pub description: String,
     pub documentation: String,
     pub repository: String,
+    pub homepage: String,
+    pub license: String,
     pub tags: Vec<String>,
+    pub categories: Vec<String>,
     pub owners: Vec<String>,
     pub readme: String,
 }
@@ -28,6 +32,37 @@ pub struct CrateVersion {
     pub version: String,
     pub downloads: u64,
     pub created_at: u64,
+    pub yanked: bool,
+}
+
+/// The metadata of a crate version shown on its page.
+#[derive(Debug, FromRow)]
+struct VersionMetadata {
+    description: Option<String>,
+    documentation: Option<String>,
+    repository: Option<String>,
+    homepage: Option<String>,
+    license: Option<String>,
+    /// The keywords as a json list
+    keywords: String,
+    /// The categories as a json list
+    categories: String,
+    readme_html: Option<String>,
+}
+
+/// Returns the version shown if none is selected: the highest stable version that
+/// is not yanked, or the highest version if there is none.
+fn default_version(versions: &[CrateVersion]) -> Option<Version> {
+    let parsed = || {
+        versions
+            .iter()
+            .filter_map(|v| Some((Version::parse(&v.version).ok()?, v.yanked)))
+    };
+    parsed()
+        .filter(|(version, yanked)| !yanked && version.pre.is_empty())
+        .map(|(version, _)| version)
+        .max()
+        .or_else(|| parsed().map(|(version, _)| version).max())
 }
 
 #[derive(Debug, FromRow)]
@@ -35,25 +70,50 @@ pub struct CrateVersion {
 pub struct Crate {
     pub id: i64,
     pub name: String,
-    pub description: String,
-    pub documentation: String,
-    pub repository: String,
+    pub description: Option<String>,
+    pub documentation: Option<String>,
+    pub repository: Option<String>,
+    pub homepage: Option<String>,
+    pub license: Option<String>,
+    /// The keywords as a json list
+    pub keywords: String,
+    /// The categories as a json list
+    pub categories: String,
 }
 
+/// Shows the latest version of a crate.
 pub async fn handler(
     State(state): State<App>,
     Path(name): Path<String>,
 ) -> Result<IndexTemplate, StatusCode> {
-    let krate: Crate = sqlx::query_as("SELECT * FROM crates WHERE name = $1")
-        .bind(&name)
-        .fetch_optional(&state.db.pool)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+    crate_page(&state, &name, None).await
+}
+
+/// Shows a specific version of a crate.
+pub async fn version_handler(
+    State(state): State<App>,
+    Path((name, version)): Path<(String, String)>,
+) -> Result<IndexTemplate, StatusCode> {
+    let version = Version::parse(&version).map_err(|_| StatusCode::NOT_FOUND)?;
+    crate_page(&state, &name, Some(version)).await
+}
+
+async fn crate_page(
+    state: &App,
+    name: &str,
+    version: Option<Version>,
+) -> Result<IndexTemplate, StatusCode> {
+    let krate: Crate =
+        sqlx::query_as("SELECT * FROM crates WHERE lower(replace(name, '_', '-')) = $1")
+            .bind(canonical_crate_name(name))
+            .fetch_optional(&state.db.pool)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
 
     let versions: Vec<CrateVersion> =
         sqlx::query_as("SELECT * FROM crate_versions WHERE name = $1")
-            .bind(&name)
+            .bind(&krate.name)
             .fetch_all(&state.db.pool)
             .await
             .unwrap(); // FIXME
@@ -68,28 +128,44 @@ pub async fn handler(
 
     let downloads = versions.iter().map(|v| v.downloads).sum::<u64>();
 
-    let latest = versions
-        .iter()
-        .filter_map(|v| Version::parse(&v.version).ok())
-        .max_by(|a, b| a.cmp(b))
-        .unwrap();
-
-    let parser = pulldown_cmark::Parser::new(README);
+    let version = match version {
+        Some(version) => version,
+        None => default_version(&versions).ok_or(StatusCode::NOT_FOUND)?,
+    };
+
+    // versions published before their metadata was stored show that of the crate
+    let metadata: VersionMetadata = sqlx::query_as(
+        "SELECT
+            coalesce(v.description, c.description) AS description,
+            coalesce(v.documentation, c.documentation) AS documentation,
+            coalesce(v.repository, c.repository) AS repository,
+            coalesce(v.homepage, c.homepage) AS homepage,
+            coalesce(v.license, c.license) AS license,
+            v.keywords, v.categories, v.readme_html
+        FROM crate_versions AS v JOIN crates AS c ON c.name = v.name
+        WHERE v.name = $1 AND v.version = $2",
+    )
+    .bind(&krate.name)
+    .bind(version.to_string())
+    .fetch_optional(&state.db.pool)
+    .await
+    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    .ok_or(StatusCode::NOT_FOUND)?;
 
-    // Write to a new String buffer.
-    let mut html_output = String::new();
-    pulldown_cmark::html::push_html(&mut html_output, parser);
     Ok(IndexTemplate {
-        name,
-        version: latest.to_string(),
+        name: krate.name,
+        version: version.to_string(),
         versions,
         downloads,
-        documentation: krate.documentation,
-        repository: krate.repository,
-        description: krate.description,
-        tags: vec!["test".into(), "crate".into(), "not-real".into()],
+        documentation: metadata.documentation.unwrap_or_default(),
+        repository: metadata.repository.unwrap_or_default(),
+        description: metadata.description.unwrap_or_default(),
+        homepage: metadata.homepage.unwrap_or_default(),
+        license: metadata.license.unwrap_or_default(),
+        tags: serde_json::from_str(&metadata.keywords).unwrap_or_default(),
+        categories: serde_json::from_str(&metadata.categories).unwrap_or_default(),
         owners,
-        readme: html_output,
+        readme: metadata.readme_html.unwrap_or_default(),
     })
 }
 
@@ -107,145 +183,98 @@ pub async fn versions_handler(
     Path(name): Path<String>,
     State(state): State<App>,
 ) -> Result<CrateVersionTemplate, StatusCode> {
-    let krate: Crate = sqlx::query_as("SELECT * FROM crates WHERE name = $1")
-        .bind(&name)
-        .fetch_optional(&state.db.pool)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+    let krate: Crate =
+        sqlx::query_as("SELECT * FROM crates WHERE lower(replace(name, '_', '-')) = $1")
+            .bind(canonical_crate_name(&name))
+            .fetch_optional(&state.db.pool)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+            .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
 
     let versions: Vec<CrateVersion> =
         sqlx::query_as("SELECT * FROM crate_versions WHERE name = $1")
-            .bind(&name)
+            .bind(&krate.name)
             .fetch_all(&state.db.pool)
             .await
             .unwrap();
 
-    let latest = versions
-        .iter()
-        .filter_map(|v| Version::parse(&v.version).ok())
-        .max_by(|a, b| a.cmp(b))
-        .unwrap();
+    let latest = default_version(&versions).ok_or(StatusCode::NOT_FOUND)?;
 
     Ok(CrateVersionTemplate {
-        name,
+        name: krate.name,
         versions,
         latest_version: latest.to_string(),
-        description: krate.description,
+        description: krate.description.unwrap_or_default(),
     })
 }
 
-const README: &str = r##"# Serde &emsp; [![Build Status]][actions] [![Latest Version]][crates.io] [![serde msrv]][Rust 1.31] [![serde_derive msrv]][Rust 1.56]
-
-[Build Status]: https://img.shields.io/github/actions/workflow/status/serde-rs/serde/ci.yml?branch=master
-[actions]: https://github.com/serde-rs/serde/actions?query=branch%3Amaster
-[Latest Version]: https://img.shields.io/crates/v/serde.svg
-[crates.io]: https://crates.io/crates/serde
-[serde msrv]: https://img.shields.io/crates/msrv/serde.svg?label=serde%20msrv&color=lightgray
-[serde_derive msrv]: https://img.shields.io/crates/msrv/serde_derive.svg?label=serde_derive%20msrv&color=lightgray
-[Rust 1.31]: https://blog.rust-lang.org/2018/12/06/Rust-1.31-and-rust-2018.html
-[Rust 1.56]: https://blog.rust-lang.org/2021/10/21/Rust-1.56.0.html
-
-**Serde is a framework for *ser*ializing and *de*serializing Rust data structures efficiently and generically.**
-
----
-
-You may be looking for:
-
-- [An overview of Serde](https://serde.rs/)
-- [Data formats supported by Serde](https://serde.rs/#data-formats)
-- [Setting up `#[derive(Serialize, Deserialize)]`](https://serde.rs/derive.html)
-- [Examples](https://serde.rs/examples.html)
-- [API documentation](https://docs.rs/serde)
-- [Release notes](https://github.com/serde-rs/serde/releases)
-
-## Serde in action
-
-<details>
-<summary>
-Click to show Cargo.toml.
-<a href="https://play.rust-lang.org/?edition=2018&gist=72755f28f99afc95e01d63174b28c1f5" target="_blank">Run this code in the playground.</a>
-</summary>
-
-```toml
-[dependencies]
-
-# The core APIs, including the Serialize and Deserialize traits. Always
-# required when using Serde. The "derive" feature is only required when
-# using #[derive(Serialize, Deserialize)] to make Serde work with structs
-# and enums defined in your crate.
-serde = { version = "1.0", features = ["derive"] }
-
-# Each data format lives in its own crate; the sample code below uses JSON
-# but you may be using a different one.
-serde_json = "1.0"
-```
-
-</details>
-<p></p>
-
-```rust
-use serde::{Deserialize, Serialize};
-
-#[derive(Serialize, Deserialize, Debug)]
-struct Point {
-    x: i32,
-    y: i32,
-}
-
-fn main() {
-    let point = Point { x: 1, y: 2 };
-
-    // Convert the Point to a JSON string.
-    let serialized = serde_json::to_string(&point).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::{crate_page, default_version, CrateVersion};
+    use crate::app::AppState;
+    use semver::Version;
+    use std::sync::Arc;
+
+    #[test]
+    fn default_versions() {
+        let versions = |versions: &[(&str, bool)]| {
+            versions
+                .iter()
+                .map(|(version, yanked)| CrateVersion {
+                    name: "abcd".into(),
+                    version: version.to_string(),
+                    downloads: 0,
+                    created_at: 0,
+                    yanked: *yanked,
+                })
+                .collect::<Vec<_>>()
+        };
+        let default =
+            |list: &[(&str, bool)]| default_version(&versions(list)).map(|v| v.to_string());
+
+        assert_eq!(
+            default(&[("1.0.0", false), ("1.1.0", true), ("2.0.0-rc.1", false)]),
+            Some("1.0.0".into())
+        );
+        assert_eq!(
+            default(&[("1.0.0", true), ("2.0.0-rc.1", false)]),
+            Some("2.0.0-rc.1".into())
+        );
+        assert_eq!(default(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn version_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = Arc::new(AppState::for_tests(dir.path()).await);
+        sqlx::query(
+            "INSERT INTO crates (id, name, description, license, keywords)
+                VALUES (1, 'abcd', 'release candidate', 'MIT', '[\"rc\"]');
+            INSERT INTO crate_versions (name, version, created_at, yanked, description, keywords)
+                VALUES ('abcd', '1.0.0', 0, false, 'stable', '[\"stable\"]'),
+                    ('abcd', '2.0.0-rc.1', 1, false, 'release candidate', '[\"rc\"]');
+            INSERT INTO crate_versions (name, version, created_at) VALUES ('abcd', '0.1.0', 0);",
+        )
+        .execute(&app.db.pool)
+        .await
+        .unwrap();
 
-    // Prints serialized = {"x":1,"y":2}
-    println!("serialized = {}", serialized);
+        let page = crate_page(&app, "abcd", None).await.unwrap();
+        assert_eq!(page.version, "1.0.0");
+        assert_eq!(page.description, "stable");
+        assert_eq!(page.tags, vec!["stable"]);
 
-    // Convert the JSON string back to a Point.
-    let deserialized: Point = serde_json::from_str(&serialized).unwrap();
+        let page = crate_page(&app, "abcd", Some(Version::parse("2.0.0-rc.1").unwrap()))
+            .await
+            .unwrap();
+        assert_eq!(page.description, "release candidate");
+        assert_eq!(page.tags, vec!["rc"]);
 
-    // Prints deserialized = Point { x: 1, y: 2 }
-    println!("deserialized = {:?}", deserialized);
+        // versions without stored metadata fall back to that of the crate
+        let page = crate_page(&app, "abcd", Some(Version::new(0, 1, 0)))
+            .await
+            .unwrap();
+        assert_eq!(page.description, "release candidate");
+        assert_eq!(page.license, "MIT");
+    }
 }
-```
-
-## Getting help
-
-Serde is one of the most widely used Rust libraries so any place that Rustaceans
-congregate will be able to help you out. For chat, consider trying the
-[#rust-questions] or [#rust-beginners] channels of the unofficial community
-Discord (invite: <https://discord.gg/rust-lang-community>), the [#rust-usage] or
-[#beginners] channels of the official Rust Project Discord (invite:
-<https://discord.gg/rust-lang>), or the [#general][zulip] stream in Zulip. For
-asynchronous, consider the [\[rust\] tag on StackOverflow][stackoverflow], the
-[/r/rust] subreddit which has a pinned weekly easy questions post, or the Rust
-[Discourse forum][discourse]. It's acceptable to file a support issue in this
-repo but they tend not to get as many eyes as any of the above and may get
-closed without a response after some time.
-
-[#rust-questions]: https://discord.com/channels/273534239310479360/274215136414400513
-[#rust-beginners]: https://discord.com/channels/273534239310479360/273541522815713281
-[#rust-usage]: https://discord.com/channels/442252698964721669/443150878111694848
-[#beginners]: https://discord.com/channels/442252698964721669/448238009733742612
-[zulip]: https://rust-lang.zulipchat.com/#narrow/stream/122651-general
-[stackoverflow]: https://stackoverflow.com/questions/tagged/rust
-[/r/rust]: https://www.reddit.com/r/rust
-[discourse]: https://users.rust-lang.org
-
-<br>
-
-#### License
-
-<sup>
-Licensed under either of <a href="LICENSE-APACHE">Apache License, Version
-2.0</a> or <a href="LICENSE-MIT">MIT license</a> at your option.
-</sup>
-
-<br>
-
-<sub>
-Unless you explicitly state otherwise, any contribution intentionally submitted
-for inclusion in Serde by you, as defined in the Apache-2.0 license, shall be
-dual licensed as above, without any additional terms or conditions.
-</sub>"##;
@@ -1,4 +1,9 @@
-use axum::{middleware::from_extractor_with_state, response::Redirect, routing::get, Router};
+use axum::{
+    middleware::from_extractor_with_state,
+    response::Redirect,
+    routing::{get, post},
+    Router,
+};
 use tower_http::services::ServeDir;
 
 use crate::{app::App, auth::RequireAuth};
@@ -12,17 +17,32 @@ mod search;
 
 pub fn router(config: &FrontendConfig, state: App) -> Router<App> {
     let auth_router = Router::new()
-        .route("/account/login", get(account::login_handler))
-        .route("/account/register", get(account::register_handler));
+        .route(
+            "/account/login",
+            get(account::login_handler).post(account::login_post_handler),
+        )
+        .route("/account/logout", post(account::logout_handler))
+        .route(
+            "/account/register",
+            get(account::register_handler).post(account::register_post_handler),
+        );
 
     let account_router = Router::new()
         .route("/account/profile", get(account::profile_handler))
-        .route("/account/token", get(account::token_handler))
+        .route(
+            "/account/token",
+            get(account::token_handler).post(account::create_token_handler),
+        )
+        .route(
+            "/account/token/:id/revoke",
+            post(account::revoke_token_handler),
+        )
         .route("/me", get(|| async { Redirect::to("/account/token") }));
 
     let crates_router = Router::new()
         .route("/crates/:name", get(crates::handler))
         .route("/crates/:name/versions", get(crates::versions_handler))
+        .route("/crates/:name/:version", get(crates::version_handler))
         .route("/crates/:name/:version/dependencies", get(|| async {}))
         .route("/crates/:name/:version/dependents", get(|| async {}));
 
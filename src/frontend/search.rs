@@ -1,10 +1,29 @@
 use askama::Template;
-use axum::extract::Query;
-use serde::{Deserialize, Serialize};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+};
+use serde::Deserialize;
+use url::form_urlencoded;
+
+use crate::{
+    app::App,
+    search::{self, SearchOptions, SortOrder},
+};
+
+/// The number of crates shown on a page.
+const PER_PAGE: u32 = 20;
 
 #[derive(Deserialize)]
 pub struct SearchQueryRequest {
+    #[serde(default)]
     q: String,
+    #[serde(default)]
+    sort: SortOrder,
+    keyword: Option<String>,
+    category: Option<String>,
+    owner: Option<String>,
+    page: Option<u32>,
 }
 
 #[derive(Template)]
@@ -12,22 +31,249 @@ pub struct SearchQueryRequest {
 pub struct SearchTemplate {
     query: String,
     results: Vec<SearchResult>,
+    total: i64,
+    page: u32,
+    pages: u32,
+    sort_links: Vec<Link>,
+    /// The active filters with links removing them.
+    filters: Vec<Link>,
+    previous_page: Option<String>,
+    next_page: Option<String>,
 }
 
-#[derive(Serialize)]
 pub struct SearchResult {
-    name: String,
+    name: Vec<Segment>,
+    /// The actual crate name, used in links.
+    link: String,
     version: String,
-    description: String,
+    description: Vec<Segment>,
+    downloads: i64,
+}
+
+/// A part of a text, which is highlighted if it matches the query.
+#[derive(Debug, PartialEq)]
+pub struct Segment {
+    text: String,
+    matched: bool,
 }
 
-pub async fn handler(Query(search): Query<SearchQueryRequest>) -> SearchTemplate {
-    SearchTemplate {
+pub struct Link {
+    label: String,
+    url: String,
+    active: bool,
+}
+
+pub async fn handler(
+    State(app): State<App>,
+    Query(search): Query<SearchQueryRequest>,
+) -> Result<SearchTemplate, StatusCode> {
+    // empty form fields do not filter
+    let non_empty = |value: Option<String>| value.filter(|value| !value.trim().is_empty());
+    let options = SearchOptions {
         query: search.q,
-        results: vec![SearchResult {
-            name: "test".into(),
-            version: "1.2.3-a.1".into(),
-            description: "This crates does not actually exist!".into(),
-        }],
+        sort: search.sort,
+        keyword: non_empty(search.keyword),
+        category: non_empty(search.category),
+        owner: non_empty(search.owner),
+        page: search.page.unwrap_or(1).max(1),
+        per_page: PER_PAGE,
+    };
+    let results = search::search(&app.db, &options)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    let terms: Vec<String> = search::terms(&options.query)
+        .map(str::to_lowercase)
+        .collect();
+    let pages = (results.total as u32).div_ceil(PER_PAGE).max(1);
+
+    let sort_links = [
+        ("Relevance", SortOrder::Relevance),
+        ("Downloads", SortOrder::Downloads),
+        ("Recent updates", SortOrder::RecentUpdates),
+    ]
+    .into_iter()
+    .map(|(label, sort)| Link {
+        label: label.into(),
+        url: search_url(&SearchOptions {
+            sort,
+            page: 1,
+            ..options.clone()
+        }),
+        active: sort == options.sort,
+    })
+    .collect();
+
+    let mut filters = Vec::new();
+    for (label, value, without) in [
+        (
+            "keyword",
+            &options.keyword,
+            SearchOptions {
+                keyword: None,
+                ..options.clone()
+            },
+        ),
+        (
+            "category",
+            &options.category,
+            SearchOptions {
+                category: None,
+                ..options.clone()
+            },
+        ),
+        (
+            "owner",
+            &options.owner,
+            SearchOptions {
+                owner: None,
+                ..options.clone()
+            },
+        ),
+    ] {
+        if let Some(value) = value {
+            filters.push(Link {
+                label: format!("{}: {}", label, value),
+                url: search_url(&SearchOptions { page: 1, ..without }),
+                active: true,
+            });
+        }
+    }
+
+    let page_url = |page: u32| {
+        search_url(&SearchOptions {
+            page,
+            ..options.clone()
+        })
+    };
+    Ok(SearchTemplate {
+        results: results
+            .hits
+            .into_iter()
+            .map(|hit| SearchResult {
+                name: highlight(&hit.name, &terms),
+                description: highlight(hit.description.as_deref().unwrap_or_default(), &terms),
+                link: hit.name,
+                version: hit.max_version,
+                downloads: hit.downloads,
+            })
+            .collect(),
+        total: results.total,
+        page: options.page,
+        pages,
+        sort_links,
+        filters,
+        previous_page: (options.page > 1).then(|| page_url(options.page - 1)),
+        next_page: (options.page < pages).then(|| page_url(options.page + 1)),
+        query: options.query,
+    })
+}
+
+/// Builds the URL of a search results page.
+fn search_url(options: &SearchOptions) -> String {
+    let mut query = form_urlencoded::Serializer::new(String::new());
+    query.append_pair("q", &options.query);
+    let sort = match options.sort {
+        SortOrder::Relevance => None,
+        SortOrder::Downloads => Some("downloads"),
+        SortOrder::RecentUpdates => Some("recent-updates"),
+    };
+    if let Some(sort) = sort {
+        query.append_pair("sort", sort);
+    }
+    for (key, value) in [
+        ("keyword", &options.keyword),
+        ("category", &options.category),
+        ("owner", &options.owner),
+    ] {
+        if let Some(value) = value {
+            query.append_pair(key, value);
+        }
+    }
+    if options.page > 1 {
+        query.append_pair("page", &options.page.to_string());
+    }
+    format!("/search?{}", query.finish())
+}
+
+/// Splits a text into segments, marking the words starting with any of the lowercase `terms`.
+fn highlight(text: &str, terms: &[String]) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut rest = text;
+    while let Some(first) = rest.chars().next() {
+        // alternate between words and the characters separating them
+        let is_word = first.is_alphanumeric();
+        let end = rest
+            .find(|c: char| c.is_alphanumeric() != is_word)
+            .unwrap_or(rest.len());
+        let (part, remaining) = rest.split_at(end);
+        rest = remaining;
+
+        let matched = is_word && {
+            let word = part.to_lowercase();
+            terms.iter().any(|term| word.starts_with(term.as_str()))
+        };
+        match segments.last_mut() {
+            Some(last) if last.matched == matched => last.text.push_str(part),
+            _ => segments.push(Segment {
+                text: part.to_string(),
+                matched,
+            }),
+        }
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{highlight, search_url, Segment};
+    use crate::search::{SearchOptions, SortOrder};
+
+    #[test]
+    fn highlight_terms() {
+        let segment = |text: &str, matched| Segment {
+            text: text.into(),
+            matched,
+        };
+        let terms = vec!["json".to_string(), "ser".to_string()];
+        assert_eq!(
+            highlight("A JSON serialization file format", &terms),
+            vec![
+                segment("A ", false),
+                segment("JSON", true),
+                segment(" ", false),
+                segment("serialization", true),
+                segment(" file format", false),
+            ]
+        );
+        assert_eq!(
+            highlight("serde_json", &terms),
+            vec![
+                segment("serde", true),
+                segment("_", false),
+                segment("json", true),
+            ]
+        );
+        assert_eq!(
+            highlight("deserialize", &terms),
+            vec![segment("deserialize", false)]
+        );
+        assert!(highlight("", &terms).is_empty());
+    }
+
+    #[test]
+    fn urls() {
+        let options = SearchOptions {
+            query: "a&b c".into(),
+            sort: SortOrder::RecentUpdates,
+            owner: Some("a@b.c".into()),
+            page: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            search_url(&options),
+            "/search?q=a%26b+c&sort=recent-updates&owner=a%40b.c&page=2"
+        );
+        assert_eq!(search_url(&SearchOptions::default()), "/search?q=");
     }
 }
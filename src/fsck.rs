@@ -0,0 +1,482 @@
+use semver::Version;
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt::{self, Display},
+};
+use valhall_index::IndexTrait;
+use valhall_models::name::canonical_crate_name;
+
+use crate::{app::AppState, tarball};
+
+/// An inconsistency between the index, the storage and the database.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    /// The index file of a crate cannot be parsed.
+    UnreadableIndexFile { name: String, error: String },
+    /// A version is stored and/or in the database, but has no index record.
+    MissingFromIndex { name: String, version: Version },
+    /// A version is indexed and/or in the database, but its `.crate` file is missing.
+    MissingFromStorage { name: String, version: Version },
+    /// A version is indexed and/or stored, but has no `crate_versions` row.
+    MissingFromDatabase { name: String, version: Version },
+    /// The checksum of an index record does not match the stored `.crate` file.
+    ChecksumMismatch {
+        name: String,
+        version: Version,
+        indexed: String,
+        stored: String,
+    },
+    /// A `crate_owners` row refers to a crate or user that does not exist.
+    OrphanedOwner {
+        id: i64,
+        crate_id: i64,
+        user_id: i64,
+    },
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnreadableIndexFile { name, error } => {
+                write!(f, "index file of crate '{}' is unreadable: {}", name, error)
+            }
+            Self::MissingFromIndex { name, version } => {
+                write!(
+                    f,
+                    "crate '{}' ({}) is missing from the index",
+                    name, version
+                )
+            }
+            Self::MissingFromStorage { name, version } => {
+                write!(
+                    f,
+                    "crate '{}' ({}) is missing from the storage",
+                    name, version
+                )
+            }
+            Self::MissingFromDatabase { name, version } => {
+                write!(
+                    f,
+                    "crate '{}' ({}) is missing from the database",
+                    name, version
+                )
+            }
+            Self::ChecksumMismatch {
+                name,
+                version,
+                indexed,
+                stored,
+            } => write!(
+                f,
+                "crate '{}' ({}) is indexed with checksum {}, but the stored file has {}",
+                name, version, indexed, stored
+            ),
+            Self::OrphanedOwner {
+                id,
+                crate_id,
+                user_id,
+            } => write!(
+                f,
+                "owner row {} refers to a missing crate ({}) or user ({})",
+                id, crate_id, user_id
+            ),
+        }
+    }
+}
+
+/// An issue found by [`fsck`] and whether it was repaired.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Finding {
+    pub issue: Issue,
+    pub repaired: bool,
+}
+
+/// Checks that the index, the storage and the database agree on all crate versions.
+///
+/// With `repair` set, missing index records are restored from the stored `.crate` files,
+/// missing database rows are recreated and orphaned owner rows are deleted.
+/// Missing `.crate` files and checksum mismatches cannot be repaired: the stored
+/// bytes are the only source of the contents, and clients have already locked
+/// the indexed checksums.
+///
+/// In proxy mode, crates that are not published locally are cached from upstream
+/// and only partially stored, so they are not checked.
+pub async fn fsck(app: &AppState, repair: bool) -> anyhow::Result<Vec<Finding>> {
+    let mut issues = Vec::new();
+
+    // the index, the storage and the database may spell the name of a crate differently
+    let local: HashSet<String> = sqlx::query_scalar::<_, String>("SELECT name FROM crates")
+        .fetch_all(&app.db.pool)
+        .await?
+        .iter()
+        .map(|name| canonical_crate_name(name))
+        .collect();
+    let is_checked =
+        |name: &str| app.proxy.is_none() || local.contains(&canonical_crate_name(name));
+
+    let mut in_database = HashMap::new();
+    let rows: Vec<(String, String, bool)> =
+        sqlx::query_as("SELECT name, version, yanked FROM crate_versions")
+            .fetch_all(&app.db.pool)
+            .await?;
+    for (name, version, yanked) in rows {
+        match Version::parse(&version) {
+            Ok(version) if is_checked(&name) => {
+                in_database.insert((name, version), yanked);
+            }
+            Ok(_) => {}
+            Err(err) => tracing::warn!(
+                "skipping invalid version '{}' of crate '{}' in the database: {}",
+                version,
+                name,
+                err
+            ),
+        }
+    }
+
+    let mut in_index = HashMap::new();
+    for name in app.index.tree().crate_names()? {
+        if !is_checked(&name) {
+            continue;
+        }
+        match app.index.all_records(&name) {
+            Ok(records) => {
+                for record in records {
+                    in_index.insert((record.name, record.version), record.checksum);
+                }
+            }
+            Err(err) => issues.push(Issue::UnreadableIndexFile {
+                name,
+                error: err.to_string(),
+            }),
+        }
+    }
+
+    let in_storage = app
+        .storage
+        .get_all_crates()?
+        .into_iter()
+        .filter(|stored| is_checked(&stored.name))
+        .map(|stored| (stored.name, stored.version))
+        .collect::<HashSet<_>>();
+
+    let versions = in_database
+        .keys()
+        .chain(in_index.keys())
+        .chain(in_storage.iter())
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    for (name, version) in versions {
+        let key = (name.clone(), version.clone());
+        if !in_index.contains_key(&key) {
+            issues.push(Issue::MissingFromIndex {
+                name: name.clone(),
+                version: version.clone(),
+            });
+        }
+        if !in_database.contains_key(&key) {
+            issues.push(Issue::MissingFromDatabase {
+                name: name.clone(),
+                version: version.clone(),
+            });
+        }
+        if !in_storage.contains(&key) {
+            issues.push(Issue::MissingFromStorage { name, version });
+        } else if let Some(indexed) = in_index.get(&key) {
+            let stored = sha256::digest(app.storage.get_crate(&name, version.clone())?);
+            if *indexed != stored {
+                issues.push(Issue::ChecksumMismatch {
+                    name,
+                    version,
+                    indexed: indexed.clone(),
+                    stored,
+                });
+            }
+        }
+    }
+
+    let orphans: Vec<(i64, i64, i64)> = sqlx::query_as(
+        "SELECT id, crate_id, user_id FROM crate_owners
+        WHERE crate_id NOT IN (SELECT id FROM crates) OR user_id NOT IN (SELECT id FROM users)",
+    )
+    .fetch_all(&app.db.pool)
+    .await?;
+    issues.extend(
+        orphans
+            .into_iter()
+            .map(|(id, crate_id, user_id)| Issue::OrphanedOwner {
+                id,
+                crate_id,
+                user_id,
+            }),
+    );
+
+    let mut findings = Vec::with_capacity(issues.len());
+    for issue in issues {
+        let repaired = repair
+            && match repair_issue(app, &issue, &in_database).await {
+                Ok(repaired) => repaired,
+                Err(err) => {
+                    tracing::warn!("failed to repair '{}': {}", issue, err);
+                    false
+                }
+            };
+        findings.push(Finding { issue, repaired });
+    }
+    Ok(findings)
+}
+
+/// Repairs an issue, returning `false` if it cannot be repaired.
+async fn repair_issue(
+    app: &AppState,
+    issue: &Issue,
+    in_database: &HashMap<(String, Version), bool>,
+) -> anyhow::Result<bool> {
+    match issue {
+        Issue::MissingFromIndex { name, version } => {
+            let Ok(bytes) = app.storage.get_crate(name, version.clone()) else {
+                return Ok(false);
+            };
+            let yanked = in_database
+                .get(&(name.clone(), version.clone()))
+                .copied()
+                .unwrap_or_default();
+            let record = tarball::read_manifest(&bytes, name, version)?
+                .into_record(sha256::digest(&bytes), yanked);
+            app.index.add_record(record)?;
+        }
+        Issue::MissingFromDatabase { name, version } => {
+            let yanked = app
+                .index
+                .all_records(name)
+                .ok()
+                .and_then(|records| records.into_iter().find(|r| r.version == *version))
+                .is_some_and(|record| record.yanked);
+
+            let mut tx = app.db.pool.begin().await?;
+            sqlx::query("INSERT OR IGNORE INTO crates (name) VALUES ($1)")
+                .bind(name)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(
+                "INSERT INTO crate_versions (name, version, created_at, yanked) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(name)
+            .bind(version.to_string())
+            .bind(chrono::Utc::now().timestamp())
+            .bind(yanked)
+            .execute(&mut *tx)
+            .await?;
+            tx.commit().await?;
+        }
+        Issue::OrphanedOwner { id, .. } => {
+            sqlx::query("DELETE FROM crate_owners WHERE id = $1")
+                .bind(id)
+                .execute(&app.db.pool)
+                .await?;
+        }
+        Issue::UnreadableIndexFile { .. }
+        | Issue::MissingFromStorage { .. }
+        | Issue::ChecksumMismatch { .. } => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fsck, Issue};
+    use crate::{
+        app::AppState,
+        crate_filter::CrateFilter,
+        proxy::{Proxy, ProxySettings},
+        tarball::tests::crate_file,
+    };
+    use semver::Version;
+    use std::time::Duration;
+    use valhall_index::IndexTrait;
+    use valhall_models::crates::CrateVersion;
+
+    fn record(name: &str, version: &Version, checksum: String) -> CrateVersion {
+        CrateVersion {
+            name: name.into(),
+            version: version.clone(),
+            dependencies: vec![],
+            checksum,
+            features: Default::default(),
+            yanked: false,
+            links: None,
+            rust_version: None,
+            features2: Default::default(),
+            v: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn check_and_repair() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = AppState::for_tests(dir.path()).await;
+        let v1 = Version::new(1, 0, 0);
+        let v2 = Version::new(2, 0, 0);
+
+        // 1.0.0 is consistent, 2.0.0 is only stored
+        for version in [&v1, &v2] {
+            let bytes = crate_file("abcd", version);
+            app.storage.store_crate("abcd", version, &bytes).unwrap();
+        }
+        let checksum = sha256::digest(crate_file("abcd", &v1));
+        app.index.add_record(record("abcd", &v1, checksum)).unwrap();
+        // a version that is indexed with the wrong checksum, but not stored
+        app.index
+            .add_record(record("efgh", &v1, "00".into()))
+            .unwrap();
+        app.storage
+            .store_crate("efgh", &v2, &crate_file("efgh", &v2))
+            .unwrap();
+        app.index
+            .add_record(record("efgh", &v2, "00".into()))
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO crates (id, name) VALUES (1, 'abcd'), (2, 'efgh');
+            INSERT INTO crate_versions (name, version, created_at, yanked) VALUES
+                ('abcd', '1.0.0', 0, false), ('abcd', '2.0.0', 0, true),
+                ('efgh', '1.0.0', 0, false), ('efgh', '2.0.0', 0, false);
+            PRAGMA foreign_keys = OFF;
+            INSERT INTO crate_owners (id, crate_id, user_id) VALUES (7, 1, 42);
+            PRAGMA foreign_keys = ON;",
+        )
+        .execute(&app.db.pool)
+        .await
+        .unwrap();
+        // a version only known to the database
+        sqlx::query(
+            "INSERT INTO crate_versions (name, version, created_at) VALUES ('xyz', '0.1.0', 0)",
+        )
+        .execute(&app.db.pool)
+        .await
+        .unwrap();
+
+        let findings = fsck(&app, false).await.unwrap();
+        let issues = findings
+            .iter()
+            .map(|finding| finding.issue.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            issues,
+            vec![
+                Issue::MissingFromIndex {
+                    name: "abcd".into(),
+                    version: v2.clone()
+                },
+                Issue::MissingFromStorage {
+                    name: "efgh".into(),
+                    version: v1.clone()
+                },
+                Issue::ChecksumMismatch {
+                    name: "efgh".into(),
+                    version: v2.clone(),
+                    indexed: "00".into(),
+                    stored: sha256::digest(crate_file("efgh", &v2)),
+                },
+                Issue::MissingFromIndex {
+                    name: "xyz".into(),
+                    version: Version::new(0, 1, 0)
+                },
+                Issue::MissingFromStorage {
+                    name: "xyz".into(),
+                    version: Version::new(0, 1, 0)
+                },
+                Issue::OrphanedOwner {
+                    id: 7,
+                    crate_id: 1,
+                    user_id: 42
+                },
+            ]
+        );
+        assert!(findings.iter().all(|finding| !finding.repaired));
+
+        let repaired = fsck(&app, true)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|finding| finding.repaired)
+            .map(|finding| finding.issue)
+            .collect::<Vec<_>>();
+        assert_eq!(repaired, vec![issues[0].clone(), issues[5].clone()]);
+        // the restored record keeps the yanked state of the database
+        let restored = app
+            .index
+            .match_record("abcd", "=2.0.0".parse().unwrap())
+            .unwrap();
+        assert!(restored.yanked);
+
+        let remaining = fsck(&app, false).await.unwrap();
+        assert_eq!(remaining.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn repair_missing_database_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = AppState::for_tests(dir.path()).await;
+        let version = Version::new(0, 3, 0);
+        let bytes = crate_file("abcd", &version);
+        app.storage.store_crate("abcd", &version, &bytes).unwrap();
+        let mut indexed = record("abcd", &version, sha256::digest(&bytes));
+        indexed.yanked = true;
+        app.index.add_record(indexed).unwrap();
+
+        let findings = fsck(&app, true).await.unwrap();
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].repaired);
+
+        let yanked: bool = sqlx::query_scalar(
+            "SELECT yanked FROM crate_versions WHERE name = 'abcd' AND version = '0.3.0'",
+        )
+        .fetch_one(&app.db.pool)
+        .await
+        .unwrap();
+        assert!(yanked);
+        assert!(fsck(&app, false).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn proxy_mode_checks_local_crates() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut app = AppState::for_tests(dir.path()).await;
+        let settings = ProxySettings {
+            connect_timeout: Duration::from_secs(1),
+            timeout: Duration::from_secs(1),
+            refresh_interval: Duration::from_secs(60),
+        };
+        app.proxy = Some(Proxy::new(
+            "sparse+http://127.0.0.1:9/",
+            CrateFilter::default(),
+            settings,
+        ));
+        let version = Version::new(1, 0, 0);
+
+        // a local crate with a mixed-case name, whose index file has a lowercase path
+        let bytes = crate_file("Serde_Foo", &version);
+        app.storage
+            .store_crate("Serde_Foo", &version, &bytes)
+            .unwrap();
+        app.index
+            .add_record(record("Serde_Foo", &version, sha256::digest(&bytes)))
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO crates (name) VALUES ('Serde_Foo');
+            INSERT INTO crate_versions (name, version, created_at) VALUES ('Serde_Foo', '1.0.0', 0);",
+        )
+        .execute(&app.db.pool)
+        .await
+        .unwrap();
+        // a crate cached from upstream is not checked
+        app.index
+            .add_record(record("cached", &version, "00".into()))
+            .unwrap();
+
+        assert_eq!(fsck(&app, false).await.unwrap(), vec![]);
+        assert_eq!(fsck(&app, true).await.unwrap(), vec![]);
+        assert_eq!(app.index.all_records("serde_foo").unwrap().len(), 1);
+    }
+}
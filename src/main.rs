@@ -1,4 +1,5 @@
 use axum::Router;
+use clap::{Parser, Subcommand};
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tower_http::trace::TraceLayer;
@@ -9,17 +10,52 @@ use valhall_config::Config;
 mod api;
 mod app;
 mod auth;
+mod crate_filter;
 mod db;
 mod error;
 mod frontend;
+mod fsck;
+mod name_policy;
+mod proxy;
+mod readme;
+mod rebuild;
+mod search;
+mod tarball;
 
 use crate::app::AppState;
 
 pub const APP_NAME: &str = env!("CARGO_PKG_NAME");
 pub const APP_VERSION: &str = env!("CARGO_PKG_VERSION");
 
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs the registry server (the default)
+    Serve,
+    /// Regenerates the index from the stored .crate files
+    RebuildIndex {
+        /// Commits the rebuilt index to the git index
+        #[arg(long)]
+        commit: bool,
+    },
+    /// Checks that the index, the storage and the database are consistent
+    Fsck {
+        /// Repairs the issues that can be repaired
+        #[arg(long)]
+        repair: bool,
+    },
+}
+
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
@@ -33,9 +69,47 @@ async fn main() {
 
     let config = Config::load("valhall.toml");
     let state = Arc::new(AppState::from_config(&config).await);
+
+    match cli.command {
+        Some(Command::RebuildIndex { commit }) => {
+            let summary = rebuild::rebuild_index(&state, commit)
+                .await
+                .expect("failed to rebuild the index");
+            tracing::info!("Rebuilt the index with {} crate versions", summary.versions);
+            if !summary.skipped.is_empty() {
+                tracing::warn!("Skipped unreadable crates: {}", summary.skipped.join(", "));
+            }
+            return;
+        }
+        Some(Command::Fsck { repair }) => {
+            let findings = fsck::fsck(&state, repair)
+                .await
+                .expect("failed to check the registry");
+            for finding in &findings {
+                if finding.repaired {
+                    tracing::info!("repaired: {}", finding.issue);
+                } else {
+                    tracing::warn!("{}", finding.issue);
+                }
+            }
+            let unrepaired = findings.iter().filter(|finding| !finding.repaired).count();
+            tracing::info!(
+                "Found {} issues, {} left unrepaired",
+                findings.len(),
+                unrepaired
+            );
+            if unrepaired > 0 {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Serve) | None => {}
+    }
+
     let app = Router::new()
         .nest("/", frontend::router(&config.frontend, state.clone()))
-        .nest("/api/v1", api::router())
+        .nest("/api/v1", api::router(&config.storage))
+        .nest("/git", api::git_router())
         .layer(TraceLayer::new_for_http())
         .with_state(state);
 
@@ -0,0 +1,138 @@
+use thiserror::Error;
+use valhall_config::NamePolicyConfig;
+use valhall_models::name::canonical_crate_name;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum NamePolicyError {
+    #[error("the crate name '{0}' is reserved")]
+    Reserved(String),
+    #[error("the crate name '{name}' is too similar to the name of the crate '{similar}'")]
+    TooSimilar { name: String, similar: String },
+}
+
+/// Decides under which names new crates may be published,
+/// based on the name policy of the index config.
+#[derive(Debug, Clone, Default)]
+pub struct NamePolicy {
+    max_distance: usize,
+    /// The canonical spellings of the protected names.
+    protected: Vec<String>,
+    /// The canonical spellings of the reserved names.
+    reserved: Vec<String>,
+    /// The canonical spellings of the names exempt from the policy.
+    allowed: Vec<String>,
+}
+
+impl NamePolicy {
+    pub fn from_config(config: &NamePolicyConfig) -> Self {
+        let canonical = |names: &[String]| {
+            names
+                .iter()
+                .map(|name| canonical_crate_name(name))
+                .collect()
+        };
+        Self {
+            max_distance: config.max_distance,
+            protected: canonical(&config.protected),
+            reserved: canonical(&config.reserved),
+            allowed: canonical(&config.allowed),
+        }
+    }
+
+    /// Checks whether a new crate may be published under `name`,
+    /// given the names of the crates that already exist in the registry.
+    pub fn check(&self, name: &str, existing: &[String]) -> Result<(), NamePolicyError> {
+        let canonical = canonical_crate_name(name);
+        if self.allowed.contains(&canonical) {
+            return Ok(());
+        }
+        if self.reserved.contains(&canonical) {
+            return Err(NamePolicyError::Reserved(name.to_string()));
+        }
+
+        let similar = self.protected.iter().chain(existing).find(|other| {
+            edit_distance(&canonical, &canonical_crate_name(other)) <= self.max_distance
+        });
+        match similar {
+            Some(similar) => Err(NamePolicyError::TooSimilar {
+                name: name.to_string(),
+                similar: similar.to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The Levenshtein distance between two names.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{edit_distance, NamePolicy, NamePolicyError};
+    use valhall_config::NamePolicyConfig;
+
+    #[test]
+    fn distance() {
+        assert_eq!(edit_distance("serde", "serde"), 0);
+        assert_eq!(edit_distance("serde", "serd"), 1);
+        assert_eq!(edit_distance("serde", "sedre"), 2);
+        assert_eq!(edit_distance("tokio", "tokyo"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
+    #[test]
+    fn check() {
+        let policy = NamePolicy::from_config(&NamePolicyConfig {
+            max_distance: 1,
+            protected: vec!["serde".into(), "tokio".into()],
+            reserved: vec!["std".into(), "proc_macro".into()],
+            allowed: vec!["Tokyo".into()],
+        });
+        let existing = &["internal-utils".to_string()];
+
+        assert_eq!(policy.check("serde-json", existing), Ok(()));
+        assert_eq!(policy.check("internal-tools", existing), Ok(()));
+        assert_eq!(
+            policy.check("Serd", existing),
+            Err(NamePolicyError::TooSimilar {
+                name: "Serd".into(),
+                similar: "serde".into()
+            })
+        );
+        assert!(policy.check("SERDE", existing).is_err());
+        assert_eq!(
+            policy.check("internal_util", existing),
+            Err(NamePolicyError::TooSimilar {
+                name: "internal_util".into(),
+                similar: "internal-utils".into()
+            })
+        );
+        assert_eq!(
+            policy.check("proc-macro", existing),
+            Err(NamePolicyError::Reserved("proc-macro".into()))
+        );
+        // overridden by the admin
+        assert_eq!(policy.check("tokyo", existing), Ok(()));
+
+        // only exact matches of protected names are rejected without a distance
+        let policy = NamePolicy::from_config(&NamePolicyConfig {
+            protected: vec!["serde".into()],
+            ..Default::default()
+        });
+        assert_eq!(policy.check("serd", existing), Ok(()));
+        assert!(policy.check("Serde", existing).is_err());
+    }
+}
@@ -0,0 +1,411 @@
+use reqwest::{header, StatusCode};
+use semver::{Version, VersionReq};
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use valhall_config::IndexConfig;
+use valhall_index::{config::RegistryConfig, tree::Tree, Index, IndexTrait};
+use valhall_storage::Storage;
+
+use crate::{
+    api::index::sparse::http_date,
+    crate_filter::{CrateFilter, FilterError},
+};
+
+#[derive(Debug, Error)]
+pub enum ProxyError {
+    #[error("upstream request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Index(#[from] valhall_index::error::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("crate '{name}' ({version}) does not exist upstream")]
+    NotFound { name: String, version: Version },
+    #[error(transparent)]
+    Rejected(#[from] FilterError),
+    #[error("checksum of crate '{name}' ({version}) does not match the upstream index")]
+    ChecksumMismatch { name: String, version: Version },
+}
+
+/// The timeouts of requests to the upstream registry and how often cached index files are refreshed.
+#[derive(Debug, Clone)]
+pub struct ProxySettings {
+    pub connect_timeout: Duration,
+    pub timeout: Duration,
+    pub refresh_interval: Duration,
+}
+
+impl ProxySettings {
+    pub fn from_config(config: &IndexConfig) -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(config.proxy_connect_timeout),
+            timeout: Duration::from_secs(config.proxy_timeout),
+            refresh_interval: Duration::from_secs(config.proxy_refresh_interval),
+        }
+    }
+}
+
+/// Fetches crates that are not published locally from an upstream sparse registry.
+///
+/// Everything fetched is cached into the local index and storage,
+/// so it can still be served if the upstream registry is unreachable.
+#[derive(Debug)]
+pub struct Proxy {
+    client: reqwest::Client,
+    /// The URL of the upstream sparse index, with a trailing slash.
+    index_url: String,
+    /// The download URL template of the upstream registry (from its `config.json`).
+    dl: OnceLock<String>,
+    /// Which upstream crate versions may be cached.
+    filter: CrateFilter,
+    refresh_interval: Duration,
+    /// When the index file of a crate was last requested from upstream.
+    refreshed: Mutex<HashMap<String, Instant>>,
+}
+
+impl Proxy {
+    pub fn new(upstream: &str, filter: CrateFilter, settings: ProxySettings) -> Self {
+        let index_url = upstream.trim_start_matches("sparse+").trim_end_matches('/');
+        let client = reqwest::Client::builder()
+            .connect_timeout(settings.connect_timeout)
+            .timeout(settings.timeout)
+            .build()
+            .expect("failed to build the upstream http client");
+        Self {
+            client,
+            index_url: format!("{}/", index_url),
+            dl: OnceLock::new(),
+            filter,
+            refresh_interval: settings.refresh_interval,
+            refreshed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Checks whether the index file of a crate is due to be refreshed from upstream.
+    ///
+    /// If so, the refresh is claimed by the caller, so concurrent reads
+    /// of the same crate do not request it from upstream again.
+    pub fn claim_refresh(&self, name: &str) -> bool {
+        let mut refreshed = self.refreshed.lock().unwrap();
+        let name = name.to_lowercase();
+        match refreshed.get(&name) {
+            Some(at) if at.elapsed() < self.refresh_interval => false,
+            _ => {
+                refreshed.insert(name, Instant::now());
+                true
+            }
+        }
+    }
+
+    /// Fetches the index file of a crate from upstream and caches it in the index.
+    ///
+    /// Versions rejected by the filter are not cached. A cached file is only
+    /// downloaded again if it was modified upstream since it was cached.
+    /// Returns `false` if the crate does not exist upstream or no version is allowed.
+    pub async fn fetch_record_file(&self, index: &Index, name: &str) -> Result<bool, ProxyError> {
+        let name = name.to_lowercase();
+        let result = self.fetch_record_file_inner(index, &name).await;
+        if result.is_err() {
+            // allow the next read to try again
+            self.refreshed.lock().unwrap().remove(&name);
+        }
+        result
+    }
+
+    async fn fetch_record_file_inner(&self, index: &Index, name: &str) -> Result<bool, ProxyError> {
+        let url = format!("{}{}", self.index_url, Tree::record_path(name));
+        tracing::debug!("fetching index file of crate '{}' from {}", name, url);
+
+        let mut request = self.client.get(url);
+        if let Ok(cached) = index.tree().read_record_file(name) {
+            request = request.header(header::IF_MODIFIED_SINCE, http_date(cached.modified));
+        }
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(true);
+        }
+        if matches!(
+            response.status(),
+            StatusCode::NOT_FOUND | StatusCode::GONE | StatusCode::UNAUTHORIZED
+        ) {
+            return Ok(false);
+        }
+        let contents = response.error_for_status()?.bytes().await?;
+        let contents = self.filter.filter_records(&contents);
+        if contents.is_empty() {
+            return Ok(false);
+        }
+        index.cache_record_file(name, &contents)?;
+
+        Ok(true)
+    }
+
+    /// Downloads a `.crate` file from upstream, verifies it against the cached
+    /// index record and stores it.
+    pub async fn fetch_crate(
+        &self,
+        index: &Index,
+        storage: &Storage,
+        name: &str,
+        version: &Version,
+    ) -> Result<Vec<u8>, ProxyError> {
+        self.filter.check(name, version)?;
+
+        let requirement = VersionReq::parse(&format!("={}", version))
+            .expect("a version is always a valid requirement");
+        let record = match index.match_record(name, requirement.clone()) {
+            Ok(record) => record,
+            Err(_) => {
+                self.fetch_record_file(index, name).await?;
+                index
+                    .match_record(name, requirement)
+                    .map_err(|_| ProxyError::NotFound {
+                        name: name.to_string(),
+                        version: version.clone(),
+                    })?
+            }
+        };
+
+        let url = download_url(
+            self.dl_template().await?,
+            &record.name,
+            version,
+            &record.checksum,
+        );
+        tracing::debug!("fetching crate '{}' ({}) from {}", name, version, url);
+        let bytes = self
+            .client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+
+        if sha256::digest(bytes.as_ref()) != record.checksum {
+            return Err(ProxyError::ChecksumMismatch {
+                name: name.to_string(),
+                version: version.clone(),
+            });
+        }
+
+        storage.store_crate(&record.name, version, &bytes)?;
+        Ok(bytes.to_vec())
+    }
+
+    async fn dl_template(&self) -> Result<&str, ProxyError> {
+        if let Some(dl) = self.dl.get() {
+            return Ok(dl);
+        }
+
+        let config: RegistryConfig = self
+            .client
+            .get(format!("{}config.json", self.index_url))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(self.dl.get_or_init(|| config.dl))
+    }
+}
+
+/// Fills in the markers of a registry's download URL template.
+///
+/// Templates without any marker get `/{crate}/{version}/download` appended, as cargo does.
+fn download_url(template: &str, name: &str, version: &Version, checksum: &str) -> String {
+    const MARKERS: [&str; 5] = [
+        "{crate}",
+        "{version}",
+        "{prefix}",
+        "{lowerprefix}",
+        "{sha256-checksum}",
+    ];
+    if !MARKERS.iter().any(|marker| template.contains(marker)) {
+        return format!(
+            "{}/{}/{}/download",
+            template.trim_end_matches('/'),
+            name,
+            version
+        );
+    }
+
+    let prefix = Tree::prefix(name);
+    template
+        .replace("{crate}", name)
+        .replace("{version}", &version.to_string())
+        .replace("{prefix}", &prefix)
+        .replace("{lowerprefix}", &prefix.to_lowercase())
+        .replace("{sha256-checksum}", checksum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{download_url, Proxy, ProxyError, ProxySettings};
+    use crate::crate_filter::CrateFilter;
+    use axum::{
+        http::{header, HeaderMap, StatusCode},
+        response::IntoResponse,
+        routing::get,
+        Router,
+    };
+    use semver::Version;
+    use std::time::{Duration, Instant};
+    use tokio::net::TcpListener;
+    use valhall_index::{sparse::SparseIndex, Index, IndexTrait};
+    use valhall_storage::Storage;
+
+    const CRATE: &[u8] = b"not really a tarball";
+
+    /// Starts a stand-in for an upstream sparse registry and returns its index URL.
+    async fn upstream() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let record = serde_json::json!({
+            "name": "abcd",
+            "vers": "1.0.0",
+            "deps": [],
+            "cksum": sha256::digest(CRATE),
+            "features": {},
+            "yanked": false,
+            "rust_version": "1.70",
+        });
+        let broken = serde_json::json!({
+            "name": "efgh",
+            "vers": "1.0.0",
+            "cksum": "0".repeat(64),
+        });
+        let dl = format!("http://{}/dl/{{crate}}/{{version}}", addr);
+
+        let app = Router::new()
+            .route(
+                "/index/config.json",
+                get(move || async move { serde_json::json!({ "dl": dl, "api": "" }).to_string() }),
+            )
+            .route(
+                "/index/ab/cd/abcd",
+                get(move |headers: HeaderMap| async move {
+                    // the record never changes
+                    match headers.contains_key(header::IF_MODIFIED_SINCE) {
+                        true => StatusCode::NOT_MODIFIED.into_response(),
+                        false => format!("{}\n", record).into_response(),
+                    }
+                }),
+            )
+            .route(
+                "/index/sl/ow/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(10)).await;
+                    ""
+                }),
+            )
+            .route(
+                "/index/ef/gh/efgh",
+                get(move || async move { format!("{}\n", broken) }),
+            )
+            .route("/dl/abcd/1.0.0", get(|| async { CRATE }))
+            .route("/dl/efgh/1.0.0", get(|| async { CRATE }));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        format!("sparse+http://{}/index/", addr)
+    }
+
+    #[tokio::test]
+    async fn fetch_and_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = Index::Sparse(SparseIndex::new(dir.path().join("index")));
+        let storage = Storage::new(dir.path().join("storage"));
+        let proxy = Proxy::new(&upstream().await, CrateFilter::default(), settings());
+
+        assert!(proxy.fetch_record_file(&index, "abcd").await.unwrap());
+        // the cached file is only revalidated
+        let cached = index.tree().read_record_file("abcd").unwrap();
+        assert!(proxy.fetch_record_file(&index, "abcd").await.unwrap());
+        assert_eq!(
+            index.tree().read_record_file("abcd").unwrap().contents,
+            cached.contents
+        );
+        assert!(!proxy.fetch_record_file(&index, "missing").await.unwrap());
+        assert_eq!(
+            index.latest_record("abcd").unwrap().version,
+            Version::new(1, 0, 0)
+        );
+        // fields unknown to the local record type are kept
+        let cached = index.tree().read_record_file("abcd").unwrap();
+        assert!(String::from_utf8(cached.contents)
+            .unwrap()
+            .contains("rust_version"));
+
+        let bytes = proxy
+            .fetch_crate(&index, &storage, "abcd", &Version::new(1, 0, 0))
+            .await
+            .unwrap();
+        assert_eq!(bytes, CRATE);
+        assert_eq!(
+            storage.get_crate("abcd", Version::new(1, 0, 0)).unwrap(),
+            CRATE
+        );
+
+        let err = proxy
+            .fetch_crate(&index, &storage, "efgh", &Version::new(1, 0, 0))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ProxyError::ChecksumMismatch { .. }));
+        assert!(storage.get_crate("efgh", Version::new(1, 0, 0)).is_err());
+    }
+
+    fn settings() -> ProxySettings {
+        ProxySettings {
+            connect_timeout: Duration::from_secs(1),
+            timeout: Duration::from_millis(200),
+            refresh_interval: Duration::from_secs(60),
+        }
+    }
+
+    #[tokio::test]
+    async fn timeout_and_refresh_interval() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = Index::Sparse(SparseIndex::new(dir.path().join("index")));
+        let proxy = Proxy::new(&upstream().await, CrateFilter::default(), settings());
+
+        assert!(proxy.claim_refresh("slow"));
+        assert!(!proxy.claim_refresh("Slow"));
+        let start = Instant::now();
+        let err = proxy.fetch_record_file(&index, "slow").await.unwrap_err();
+        assert!(matches!(err, ProxyError::Http(_)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // a failed refresh is retried by the next read
+        assert!(proxy.claim_refresh("slow"));
+    }
+
+    #[test]
+    fn download_urls() {
+        let version = Version::new(1, 2, 3);
+        assert_eq!(
+            download_url("https://static.crates.io/crates", "serde", &version, "ff"),
+            "https://static.crates.io/crates/serde/1.2.3/download"
+        );
+        assert_eq!(
+            download_url(
+                "https://dl.example.com/{prefix}/{crate}-{version}.crate?sum={sha256-checksum}",
+                "Serde",
+                &version,
+                "ff"
+            ),
+            "https://dl.example.com/Se/rd/Serde-1.2.3.crate?sum=ff"
+        );
+        assert_eq!(
+            download_url(
+                "https://dl.example.com/{lowerprefix}/{crate}",
+                "Abc",
+                &version,
+                "ff"
+            ),
+            "https://dl.example.com/3/a/Abc"
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use ammonia::{UrlRelative, UrlRelativeEvaluate};
+use pulldown_cmark::{Options, Parser};
+use std::{
+    borrow::Cow,
+    path::{Component, Path},
+};
+use url::Url;
+
+/// The README packaged by cargo if the manifest does not name one.
+const DEFAULT_README: &str = "README.md";
+
+/// The maximum size of a README that is rendered, in bytes.
+pub const MAX_README_SIZE: u64 = 1024 * 1024;
+
+/// Returns the path of the README in the `.crate` file.
+///
+/// A README outside of the package (e.g. `../README.md` in a workspace)
+/// is packaged by cargo at the root of the crate.
+pub fn packaged_path(readme_file: Option<&str>) -> String {
+    let path = Path::new(readme_file.unwrap_or(DEFAULT_README));
+    if !path.components().any(|c| c == Component::ParentDir) {
+        return path.display().to_string();
+    }
+    match path.file_name() {
+        Some(file_name) => file_name.to_string_lossy().into_owned(),
+        None => DEFAULT_README.to_string(),
+    }
+}
+
+/// Renders the README of a crate to sanitized HTML.
+///
+/// `path` is the path of the README in the crate. Relative links and images are
+/// resolved against the `repository` of the crate, or removed if it has none.
+/// READMEs that are not markdown files are shown as plain text.
+pub fn render(contents: &str, path: &str, repository: Option<&str>) -> String {
+    let is_markdown = Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("md") || ext.eq_ignore_ascii_case("markdown"));
+    if !is_markdown {
+        return format!("<pre>{}</pre>", ammonia::clean_text(contents));
+    }
+
+    // relative paths are relative to the directory of the README
+    let dir = match Path::new(path).parent() {
+        Some(parent) if parent != Path::new("") => format!("{}/", parent.display()),
+        _ => String::new(),
+    };
+    let urls = repository.and_then(RepositoryUrls::new);
+
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS;
+    let parser = Parser::new_ext(contents, options);
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+
+    // images are served raw, while links point to the rendered file of the repository
+    let raw = urls.as_ref().map(|urls| urls.raw.clone());
+    let image_dir = dir.clone();
+    let links = RelativeLinks {
+        base: urls.map(|urls| urls.blob),
+        dir,
+    };
+    ammonia::Builder::default()
+        .add_tag_attributes("code", &["class"])
+        .attribute_filter(
+            move |element, attribute, value| match (element, attribute, &raw) {
+                ("img", "src", Some(raw)) => {
+                    Some(resolve(raw, &image_dir, value).map_or(Cow::Borrowed(value), Cow::Owned))
+                }
+                _ => Some(Cow::Borrowed(value)),
+            },
+        )
+        .url_relative(UrlRelative::Custom(Box::new(links)))
+        .clean(&html)
+        .to_string()
+}
+
+/// The URLs under which the files of a repository are available.
+struct RepositoryUrls {
+    /// The rendered files at the default branch.
+    blob: String,
+    /// The raw files at the default branch.
+    raw: String,
+}
+
+impl RepositoryUrls {
+    fn new(repository: &str) -> Option<Self> {
+        let repository = repository.trim_end_matches('/').trim_end_matches(".git");
+        let url = Url::parse(repository).ok()?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return None;
+        }
+
+        Some(match url.host_str() {
+            Some("github.com") => Self {
+                blob: format!("{}/blob/HEAD", repository),
+                raw: format!("https://raw.githubusercontent.com{}/HEAD", url.path()),
+            },
+            Some("gitlab.com") => Self {
+                blob: format!("{}/-/blob/HEAD", repository),
+                raw: format!("{}/-/raw/HEAD", repository),
+            },
+            _ => Self {
+                blob: repository.to_string(),
+                raw: repository.to_string(),
+            },
+        })
+    }
+}
+
+/// Resolves a relative `url` of a README in `dir` against the `base` of the repository.
+///
+/// Returns `None` if the URL is not relative.
+fn resolve(base: &str, dir: &str, url: &str) -> Option<String> {
+    if url.starts_with('#') || url.starts_with("//") || Url::parse(url).is_ok() {
+        return None;
+    }
+    let base = match url.starts_with('/') {
+        true => format!("{}/", base),
+        false => format!("{}/{}", base, dir),
+    };
+    let resolved = Url::parse(&base)
+        .ok()?
+        .join(url.trim_start_matches('/'))
+        .ok()?;
+    Some(resolved.into())
+}
+
+/// Rewrites the relative URLs left in the HTML, i.e. those of links, as images are resolved before.
+struct RelativeLinks {
+    base: Option<String>,
+    dir: String,
+}
+
+impl<'a> UrlRelativeEvaluate<'a> for RelativeLinks {
+    fn evaluate<'url>(&self, url: &'url str) -> Option<Cow<'url, str>> {
+        if url.starts_with('#') {
+            return Some(Cow::Borrowed(url));
+        }
+        let base = self.base.as_ref()?;
+        resolve(base, &self.dir, url).map(Cow::Owned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{packaged_path, render};
+
+    const GITHUB: Option<&str> = Some("https://github.com/owner/repo.git");
+
+    #[test]
+    fn rewrite_relative_urls() {
+        let html = render(
+            "[guide](docs/guide.md) [top](#usage) [root](/LICENSE) [web](https://example.com)\n\n\
+             ![logo](assets/logo.png)",
+            "README.md",
+            GITHUB,
+        );
+        assert!(html.contains(r#"href="https://github.com/owner/repo/blob/HEAD/docs/guide.md""#));
+        assert!(html.contains(r##"href="#usage""##));
+        assert!(html.contains(r#"href="https://github.com/owner/repo/blob/HEAD/LICENSE""#));
+        assert!(html.contains(r#"href="https://example.com""#));
+        assert!(html.contains(
+            r#"src="https://raw.githubusercontent.com/owner/repo/HEAD/assets/logo.png""#
+        ));
+
+        // relative to the directory of the README
+        let html = render(
+            "[up](../CHANGELOG.md) ![img](img.svg)",
+            "crates/abc/README.md",
+            GITHUB,
+        );
+        assert!(
+            html.contains(r#"href="https://github.com/owner/repo/blob/HEAD/crates/CHANGELOG.md""#)
+        );
+        assert!(html.contains(
+            r#"src="https://raw.githubusercontent.com/owner/repo/HEAD/crates/abc/img.svg""#
+        ));
+
+        let html = render(
+            "[gitlab](src/lib.rs) ![img](img.png)",
+            "README.md",
+            Some("https://gitlab.com/group/repo"),
+        );
+        assert!(html.contains(r#"href="https://gitlab.com/group/repo/-/blob/HEAD/src/lib.rs""#));
+        assert!(html.contains(r#"src="https://gitlab.com/group/repo/-/raw/HEAD/img.png""#));
+
+        // without a repository, relative URLs cannot be resolved
+        let html = render(
+            "[guide](docs/guide.md) ![logo](logo.png)",
+            "README.md",
+            None,
+        );
+        assert!(!html.contains("docs/guide.md"));
+        assert!(!html.contains("logo.png"));
+    }
+
+    #[test]
+    fn readme_path() {
+        assert_eq!(packaged_path(None), "README.md");
+        assert_eq!(packaged_path(Some("docs/README.md")), "docs/README.md");
+        assert_eq!(packaged_path(Some("../../README.md")), "README.md");
+    }
+
+    #[test]
+    fn sanitize() {
+        let html = render(
+            "# Title\n\n<script>alert(1)</script>\n\n<a href=\"javascript:alert(1)\" onclick=\"x()\">x</a>\n\n\
+             <img src=\"raw.png\">\n\n```rust\nfn main() {}\n```",
+            "README.md",
+            GITHUB,
+        );
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("javascript:"));
+        assert!(!html.contains("onclick"));
+        assert!(html.contains(r#"src="https://raw.githubusercontent.com/owner/repo/HEAD/raw.png""#));
+        assert!(html.contains(r#"<code class="language-rust">"#));
+
+        assert_eq!(
+            render("<b>not markdown</b>", "README.txt", GITHUB),
+            "<pre>&lt;b&gt;not&#32;markdown&lt;&#47;b&gt;</pre>"
+        );
+    }
+}
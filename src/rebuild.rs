@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+
+use crate::{app::AppState, tarball};
+
+/// The outcome of an index rebuild.
+#[derive(Debug, Default, PartialEq)]
+pub struct RebuildSummary {
+    /// The number of crate versions written to the index.
+    pub versions: usize,
+    /// The stored crate versions that could not be read and are missing from the index.
+    pub skipped: Vec<String>,
+}
+
+/// Regenerates all index records from the `.crate` files in the storage.
+///
+/// The checksums are recomputed from the stored files and the yanked state
+/// is taken from the database. If `commit` is set, the git index commits the result.
+pub async fn rebuild_index(app: &AppState, commit: bool) -> anyhow::Result<RebuildSummary> {
+    let yanked: HashSet<(String, String)> =
+        sqlx::query_as("SELECT name, version FROM crate_versions WHERE yanked")
+            .fetch_all(&app.db.pool)
+            .await?
+            .into_iter()
+            .collect();
+
+    let mut summary = RebuildSummary::default();
+    let mut records = Vec::new();
+    for stored in app.storage.get_all_crates()? {
+        let bytes = app
+            .storage
+            .get_crate(&stored.name, stored.version.clone())?;
+        let manifest = match tarball::read_manifest(&bytes, &stored.name, &stored.version) {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                tracing::warn!(
+                    "skipping crate '{}' ({}): {}",
+                    stored.name,
+                    stored.version,
+                    err
+                );
+                summary
+                    .skipped
+                    .push(format!("{}-{}", stored.name, stored.version));
+                continue;
+            }
+        };
+
+        let is_yanked = yanked.contains(&(stored.name.clone(), stored.version.to_string()));
+        records.push(manifest.into_record(sha256::digest(&bytes), is_yanked));
+    }
+
+    summary.versions = records.len();
+    app.index.rebuild(records, commit)?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::rebuild_index;
+    use crate::{app::AppState, tarball::tests::crate_file};
+    use semver::Version;
+    use valhall_index::IndexTrait;
+
+    #[tokio::test]
+    async fn rebuild_from_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = AppState::for_tests(dir.path()).await;
+        for version in [Version::new(1, 0, 0), Version::new(0, 9, 0)] {
+            let bytes = crate_file("abcd", &version);
+            app.storage.store_crate("abcd", &version, &bytes).unwrap();
+        }
+        app.storage
+            .store_crate("broken", &Version::new(1, 0, 0), b"not a tarball")
+            .unwrap();
+
+        sqlx::query(
+            "INSERT INTO crate_versions (name, version, created_at, yanked) VALUES ('abcd', '0.9.0', 0, true)",
+        )
+        .execute(&app.db.pool)
+        .await
+        .unwrap();
+        // a stale record that is not backed by a stored crate
+        app.index
+            .tree()
+            .write_record_file("stale", br#"{"name":"stale","vers":"1.0.0","cksum":"00"}"#)
+            .unwrap();
+
+        let summary = rebuild_index(&app, false).await.unwrap();
+        assert_eq!(summary.versions, 2);
+        assert_eq!(summary.skipped, vec!["broken-1.0.0"]);
+
+        let records = app.index.all_records("abcd").unwrap();
+        assert_eq!(records[0].version, Version::new(0, 9, 0));
+        assert!(records[0].yanked);
+        assert!(!records[1].yanked);
+        assert_eq!(
+            records[1].checksum,
+            sha256::digest(
+                app.storage
+                    .get_crate("abcd", Version::new(1, 0, 0))
+                    .unwrap()
+            )
+        );
+        assert_eq!(records[1].dependencies[0].name, "serde");
+        assert!(app.index.all_records("stale").is_err());
+    }
+}
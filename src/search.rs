@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use sqlx::{QueryBuilder, Sqlite};
+use valhall_models::name::canonical_crate_name;
+
+use crate::db::Database;
+
+/// A crate matching a search query.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SearchHit {
+    pub name: String,
+    /// The highest version that is not yanked, or the highest version if all are yanked.
+    pub max_version: String,
+    pub description: Option<String>,
+    /// The downloads of all versions.
+    pub downloads: i64,
+    /// When the latest version was published, as a unix timestamp.
+    #[serde(skip)]
+    pub updated_at: Option<i64>,
+}
+
+/// One page of the crates matching a search query.
+#[derive(Debug, PartialEq)]
+pub struct SearchResults {
+    pub hits: Vec<SearchHit>,
+    /// The number of matching crates on all pages.
+    pub total: i64,
+}
+
+/// The order of the search results.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortOrder {
+    /// The best matches first, or by name if there is no query.
+    #[default]
+    Relevance,
+    /// The most downloaded crates first.
+    Downloads,
+    /// The crates with the most recently published version first.
+    RecentUpdates,
+}
+
+/// What to search for and how to present the results.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchOptions {
+    pub query: String,
+    pub sort: SortOrder,
+    /// Only crates with this keyword.
+    pub keyword: Option<String>,
+    /// Only crates in this category.
+    pub category: Option<String>,
+    /// Only crates owned by the user with this name or email.
+    pub owner: Option<String>,
+    /// The page of the results, starting at 1.
+    pub page: u32,
+    pub per_page: u32,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            query: String::new(),
+            sort: SortOrder::default(),
+            keyword: None,
+            category: None,
+            owner: None,
+            page: 1,
+            per_page: 10,
+        }
+    }
+}
+
+/// Searches the names, descriptions and keywords of all crates.
+///
+/// Every word of the query has to match the beginning of a word of the crate.
+/// Sorted by relevance, a crate whose name is the query comes first and the others
+/// are ranked with matches in the name counting the most. An empty query lists all crates.
+pub async fn search(db: &Database, options: &SearchOptions) -> sqlx::Result<SearchResults> {
+    let expression = match_expression(&options.query);
+
+    let mut select = QueryBuilder::new(
+        "SELECT crates.name, crates.description,
+            (SELECT coalesce(sum(downloads), 0) FROM crate_versions
+                WHERE crate_versions.name = crates.name) AS downloads,
+            (SELECT max(created_at) FROM crate_versions
+                WHERE crate_versions.name = crates.name) AS updated_at",
+    );
+    push_filters(&mut select, options, expression.as_deref());
+    select.push(" ORDER BY ");
+    match (options.sort, &expression) {
+        (SortOrder::Relevance, Some(_)) => {
+            select
+                .push("lower(replace(crates.name, '_', '-')) = ")
+                .push_bind(canonical_crate_name(options.query.trim()))
+                .push(" DESC, bm25(crates_fts, 10.0, 1.0, 3.0), ");
+        }
+        (SortOrder::Relevance, None) => {}
+        (SortOrder::Downloads, _) => {
+            select.push("downloads DESC, ");
+        }
+        (SortOrder::RecentUpdates, _) => {
+            select.push("updated_at DESC, ");
+        }
+    }
+    select
+        .push("crates.name LIMIT ")
+        .push_bind(options.per_page)
+        .push(" OFFSET ")
+        .push_bind(i64::from(options.page.max(1) - 1) * i64::from(options.per_page));
+    let rows: Vec<(String, Option<String>, i64, Option<i64>)> =
+        select.build_query_as().fetch_all(&db.pool).await?;
+
+    let mut count = QueryBuilder::new("SELECT COUNT(*)");
+    push_filters(&mut count, options, expression.as_deref());
+    let total = count.build_query_scalar().fetch_one(&db.pool).await?;
+
+    // the versions of all hits are fetched at once to find their latest versions
+    let mut versions = HashMap::<String, Vec<(String, bool)>>::new();
+    if !rows.is_empty() {
+        let mut select =
+            QueryBuilder::new("SELECT name, version, yanked FROM crate_versions WHERE name IN (");
+        let mut names = select.separated(", ");
+        for (name, ..) in &rows {
+            names.push_bind(name);
+        }
+        select.push(")");
+        let version_rows: Vec<(String, String, bool)> =
+            select.build_query_as().fetch_all(&db.pool).await?;
+        for (name, version, yanked) in version_rows {
+            versions.entry(name).or_default().push((version, yanked));
+        }
+    }
+
+    let hits = rows
+        .into_iter()
+        .map(|(name, description, downloads, updated_at)| SearchHit {
+            max_version: max_version(versions.get(&name).map_or(&[], Vec::as_slice)),
+            name,
+            description,
+            downloads,
+            updated_at,
+        })
+        .collect();
+
+    Ok(SearchResults { hits, total })
+}
+
+/// Adds the tables and conditions selecting the matching crates to a query.
+fn push_filters<'a>(
+    builder: &mut QueryBuilder<'a, Sqlite>,
+    options: &'a SearchOptions,
+    expression: Option<&'a str>,
+) {
+    match expression {
+        Some(expression) => {
+            builder
+                .push(" FROM crates_fts JOIN crates ON crates.id = crates_fts.rowid")
+                .push(" WHERE crates_fts MATCH ")
+                .push_bind(expression);
+        }
+        None => {
+            builder.push(" FROM crates WHERE true");
+        }
+    }
+    if let Some(keyword) = &options.keyword {
+        builder
+            .push(
+                " AND EXISTS (SELECT 1 FROM json_each(crates.keywords) WHERE lower(value) = lower(",
+            )
+            .push_bind(keyword)
+            .push("))");
+    }
+    if let Some(category) = &options.category {
+        builder
+            .push(" AND EXISTS (SELECT 1 FROM json_each(crates.categories) WHERE lower(value) = lower(")
+            .push_bind(category)
+            .push("))");
+    }
+    if let Some(owner) = &options.owner {
+        builder
+            .push(
+                " AND EXISTS (SELECT 1 FROM crate_owners JOIN users ON users.id = crate_owners.user_id
+                    WHERE crate_owners.crate_id = crates.id AND (users.name = ",
+            )
+            .push_bind(owner)
+            .push(" OR users.email = ")
+            .push_bind(owner)
+            .push("))");
+    }
+}
+
+/// Turns a search query into an FTS5 expression matching every word as a prefix.
+///
+/// Returns `None` if the query does not contain any words.
+fn match_expression(query: &str) -> Option<String> {
+    // quoting every word keeps FTS5 operators and syntax in the query from being interpreted
+    let words: Vec<String> = terms(query).map(|word| format!("\"{}\"*", word)).collect();
+    (!words.is_empty()).then(|| words.join(" "))
+}
+
+/// Splits a text into words the way the search index does.
+pub fn terms(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+}
+
+/// Returns the highest of the `(version, yanked)` pairs that is not yanked,
+/// or the highest version if all are yanked.
+pub fn max_version(versions: &[(String, bool)]) -> String {
+    let parsed = || {
+        versions
+            .iter()
+            .filter_map(|(version, yanked)| Some((Version::parse(version).ok()?, *yanked)))
+    };
+    parsed()
+        .filter(|(_, yanked)| !yanked)
+        .map(|(version, _)| version)
+        .max()
+        .or_else(|| parsed().map(|(version, _)| version).max())
+        .map(|version| version.to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{match_expression, search, SearchOptions, SearchResults, SortOrder};
+    use crate::db::Database;
+
+    async fn find(db: &Database, query: &str, page: u32, per_page: u32) -> SearchResults {
+        let options = SearchOptions {
+            query: query.into(),
+            page,
+            per_page,
+            ..Default::default()
+        };
+        search(db, &options).await.unwrap()
+    }
+
+    async fn database() -> Database {
+        let db = Database::in_memory().await.unwrap();
+        let crates = [
+            (
+                "serde",
+                "A generic serialization/deserialization framework",
+                r#"["serde","serialization","no_std"]"#,
+            ),
+            (
+                "serde_json",
+                "A JSON serialization file format",
+                r#"["json","serde","serialization"]"#,
+            ),
+            (
+                "json-patch",
+                "RFC 6902, JavaScript Object Notation (JSON) Patch",
+                r#"["json","patch"]"#,
+            ),
+            (
+                "tokio",
+                "An event-driven, non-blocking I/O platform",
+                r#"["io","async","non-blocking"]"#,
+            ),
+        ];
+        for (name, description, keywords) in crates {
+            sqlx::query("INSERT INTO crates (name, description, keywords) VALUES ($1, $2, $3)")
+                .bind(name)
+                .bind(description)
+                .bind(keywords)
+                .execute(&db.pool)
+                .await
+                .unwrap();
+        }
+        for (name, version, yanked) in [
+            ("serde", "1.0.9", false),
+            ("serde", "1.0.10", false),
+            ("serde", "1.1.0", true),
+            ("serde_json", "0.1.0", true),
+        ] {
+            sqlx::query(
+                "INSERT INTO crate_versions (name, version, created_at, yanked) VALUES ($1, $2, 0, $3)",
+            )
+            .bind(name)
+            .bind(version)
+            .bind(yanked)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        }
+        db
+    }
+
+    fn names(results: &super::SearchResults) -> Vec<&str> {
+        results.hits.iter().map(|hit| hit.name.as_str()).collect()
+    }
+
+    #[tokio::test]
+    async fn rank_and_paginate() {
+        let db = database().await;
+
+        // the exact name match comes first
+        assert_eq!(find(&db, "json", 1, 10).await.total, 2);
+        let results = find(&db, "serde", 1, 10).await;
+        assert_eq!(names(&results), vec!["serde", "serde_json"]);
+        assert_eq!(results.hits[0].max_version, "1.0.10");
+        assert_eq!(results.hits[1].max_version, "0.1.0");
+        let results = find(&db, "SERDE-JSON", 1, 10).await;
+        assert_eq!(names(&results)[0], "serde_json");
+
+        // words match as prefixes and all of them have to match
+        assert_eq!(names(&find(&db, "seria", 1, 10).await).len(), 2);
+        let results = find(&db, "serialization format", 1, 10).await;
+        assert_eq!(names(&results), vec!["serde_json"]);
+        assert_eq!(find(&db, "xml", 1, 10).await.total, 0);
+
+        let page = find(&db, "", 2, 3).await;
+        assert_eq!(names(&page), vec!["tokio"]);
+        assert_eq!(page.total, 4);
+        let page = find(&db, "serde", 2, 1).await;
+        assert_eq!(names(&page), vec!["serde_json"]);
+        assert_eq!(page.total, 2);
+    }
+
+    #[tokio::test]
+    async fn sort_and_filter() {
+        let db = database().await;
+        for statement in [
+            "UPDATE crate_versions SET downloads = 5, created_at = 10 WHERE name = 'serde'",
+            "INSERT INTO crate_versions (name, version, created_at, downloads) VALUES ('tokio', '1.0.0', 20, 16)",
+            "UPDATE crates SET categories = '[\"Encoding\"]' WHERE name LIKE 'serde%'",
+            "INSERT INTO users (id, email, name, password) VALUES (1, 'a@b.c', 'alice', '')",
+            "INSERT INTO crate_owners (crate_id, user_id) SELECT id, 1 FROM crates WHERE name LIKE '%json%'",
+        ] {
+            sqlx::query(statement).execute(&db.pool).await.unwrap();
+        }
+        let find = |options: SearchOptions| {
+            let db = &db;
+            async move { names(&search(db, &options).await.unwrap()).join(" ") }
+        };
+
+        let sorted = |sort| SearchOptions {
+            sort,
+            ..Default::default()
+        };
+        assert_eq!(
+            find(sorted(SortOrder::Downloads)).await,
+            "tokio serde json-patch serde_json"
+        );
+        assert_eq!(
+            find(sorted(SortOrder::RecentUpdates)).await,
+            "tokio serde serde_json json-patch"
+        );
+
+        let keyword = SearchOptions {
+            keyword: Some("JSON".into()),
+            ..Default::default()
+        };
+        assert_eq!(find(keyword).await, "json-patch serde_json");
+        let category = SearchOptions {
+            query: "json".into(),
+            category: Some("encoding".into()),
+            ..Default::default()
+        };
+        assert_eq!(find(category).await, "serde_json");
+        let owner = SearchOptions {
+            owner: Some("a@b.c".into()),
+            sort: SortOrder::Downloads,
+            ..Default::default()
+        };
+        assert_eq!(find(owner).await, "json-patch serde_json");
+    }
+
+    #[tokio::test]
+    async fn index_follows_changes() {
+        let db = database().await;
+        sqlx::query("UPDATE crates SET description = 'runtime' WHERE name = 'tokio'")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        sqlx::query("DELETE FROM crates WHERE name = 'json-patch'")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        assert_eq!(names(&find(&db, "runtime", 1, 10).await), vec!["tokio"]);
+        assert_eq!(find(&db, "event", 1, 10).await.total, 0);
+        assert_eq!(names(&find(&db, "json", 1, 10).await), vec!["serde_json"]);
+    }
+
+    #[test]
+    fn escape_query() {
+        assert_eq!(
+            match_expression("serde json"),
+            Some(r#""serde"* "json"*"#.into())
+        );
+        assert_eq!(
+            match_expression(r#"a" OR name:b*"#),
+            Some(r#""a"* "OR"* "name"* "b"*"#.into())
+        );
+        assert_eq!(match_expression(" -_ "), None);
+    }
+}
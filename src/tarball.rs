@@ -0,0 +1,598 @@
+use flate2::read::GzDecoder;
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Read,
+    path::{Component, Path},
+};
+use tar::EntryType;
+use thiserror::Error;
+use valhall_models::crates::{CrateDependency, CrateDependencyKind, CrateVersion};
+
+#[derive(Debug, Error)]
+pub enum TarballError {
+    #[error("failed to read the crate tarball: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("the crate tarball does not contain '{0}'")]
+    MissingManifest(String),
+    #[error("invalid Cargo.toml: {0}")]
+    InvalidManifest(#[from] toml::de::Error),
+    #[error("the crate tarball is larger than the limit of {limit} bytes")]
+    TooLarge { limit: u64 },
+    #[error("the unpacked crate tarball is larger than the limit of {limit} bytes")]
+    UnpackedTooLarge { limit: u64 },
+    #[error("the crate tarball contains '{path}' outside of the '{root}' directory")]
+    OutsideRoot { path: String, root: String },
+    #[error("the link '{path}' in the crate tarball points outside of the crate")]
+    LinkEscapesRoot { path: String },
+    #[error("the crate tarball contains '{path}', which is not a file, directory or link")]
+    UnsupportedEntry { path: String },
+    #[error("'{path}' in the crate tarball is larger than the limit of {limit} bytes")]
+    FileTooLarge { path: String, limit: u64 },
+    #[error("the Cargo.toml describes '{found}', but '{expected}' was published")]
+    ManifestMismatch { expected: String, found: String },
+}
+
+/// The size limits of uploaded `.crate` files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TarballLimits {
+    /// The maximum size of the gzipped tarball in bytes.
+    pub max_size: u64,
+    /// The maximum size of the unpacked tarball in bytes.
+    pub max_unpacked_size: u64,
+}
+
+impl Default for TarballLimits {
+    fn default() -> Self {
+        Self {
+            max_size: 10 * 1024 * 1024,
+            max_unpacked_size: 512 * 1024 * 1024,
+        }
+    }
+}
+
+/// The parts of a normalized `Cargo.toml` (as packaged by `cargo publish`) needed for the index.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Manifest {
+    pub package: Package,
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, Dependency>,
+    #[serde(default, alias = "dev_dependencies")]
+    pub dev_dependencies: BTreeMap<String, Dependency>,
+    #[serde(default, alias = "build_dependencies")]
+    pub build_dependencies: BTreeMap<String, Dependency>,
+    /// The platform specific dependencies, keyed by the target (e.g. `cfg(windows)`).
+    #[serde(default)]
+    pub target: BTreeMap<String, TargetDependencies>,
+    #[serde(default)]
+    pub features: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Package {
+    pub name: String,
+    pub version: Version,
+    pub links: Option<String>,
+    #[serde(rename = "rust-version")]
+    pub rust_version: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TargetDependencies {
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, Dependency>,
+    #[serde(default, alias = "dev_dependencies")]
+    pub dev_dependencies: BTreeMap<String, Dependency>,
+    #[serde(default, alias = "build_dependencies")]
+    pub build_dependencies: BTreeMap<String, Dependency>,
+}
+
+/// A dependency, either as a plain version requirement or as a table.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Dependency {
+    Simple(VersionReq),
+    Detailed(DependencyDetail),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DependencyDetail {
+    pub version: Option<VersionReq>,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default)]
+    pub optional: bool,
+    #[serde(alias = "default_features")]
+    pub default_features: Option<bool>,
+    /// The actual name of a renamed dependency.
+    pub package: Option<String>,
+    /// The index of the registry the dependency comes from, if it is not this one.
+    pub registry_index: Option<String>,
+}
+
+/// Reads the `Cargo.toml` of the crate version `name`-`version` from a gzipped `.crate` tarball.
+pub fn read_manifest(
+    bytes: &[u8],
+    name: &str,
+    version: &Version,
+) -> Result<Manifest, TarballError> {
+    // the manifest is only bounded by the unpacked size checked by `verify`
+    match read_file(bytes, name, version, "Cargo.toml", u64::MAX)? {
+        Some(contents) => Ok(toml::from_str(&contents)?),
+        None => Err(TarballError::MissingManifest(format!(
+            "{}-{}/Cargo.toml",
+            name, version
+        ))),
+    }
+}
+
+/// Reads a text file of a packaged crate, `path` is relative to the crate root.
+///
+/// Returns `None` if the tarball does not contain the file,
+/// and fails without unpacking it if it is larger than `max_size` bytes.
+pub fn read_file(
+    bytes: &[u8],
+    name: &str,
+    version: &Version,
+    path: &str,
+    max_size: u64,
+) -> Result<Option<String>, TarballError> {
+    let file_path = Path::new(&format!("{}-{}", name, version)).join(path);
+
+    let mut archive = tar::Archive::new(GzDecoder::new(bytes));
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if entry.path()? != file_path {
+            continue;
+        }
+        if entry.size() > max_size {
+            return Err(TarballError::FileTooLarge {
+                path: file_path.display().to_string(),
+                limit: max_size,
+            });
+        }
+        let mut contents = Vec::new();
+        entry.take(max_size).read_to_end(&mut contents)?;
+        return Ok(Some(String::from_utf8_lossy(&contents).into_owned()));
+    }
+
+    Ok(None)
+}
+
+/// Verifies an uploaded `.crate` tarball and returns its `Cargo.toml`.
+///
+/// Every entry has to be inside the `{name}-{version}/` root directory, links must not
+/// point outside of it and the `Cargo.toml` has to describe the published crate version.
+pub fn verify(
+    bytes: &[u8],
+    name: &str,
+    version: &Version,
+    limits: &TarballLimits,
+) -> Result<Manifest, TarballError> {
+    if bytes.len() as u64 > limits.max_size {
+        return Err(TarballError::TooLarge {
+            limit: limits.max_size,
+        });
+    }
+
+    let root = format!("{}-{}", name, version);
+    let mut reader = LimitedReader {
+        inner: GzDecoder::new(bytes),
+        remaining: limits.max_unpacked_size,
+        exceeded: false,
+    };
+    let result = verify_entries(&mut reader, &root);
+    // the limit surfaces as whatever error the read exceeding it caused
+    if reader.exceeded {
+        return Err(TarballError::UnpackedTooLarge {
+            limit: limits.max_unpacked_size,
+        });
+    }
+    let manifest =
+        result?.ok_or_else(|| TarballError::MissingManifest(format!("{}/Cargo.toml", root)))?;
+
+    if manifest.package.name != name || manifest.package.version != *version {
+        return Err(TarballError::ManifestMismatch {
+            expected: format!("{} ({})", name, version),
+            found: format!("{} ({})", manifest.package.name, manifest.package.version),
+        });
+    }
+    Ok(manifest)
+}
+
+/// Checks all entries of the tarball, returning the parsed `Cargo.toml` if there is one.
+fn verify_entries(reader: impl Read, root: &str) -> Result<Option<Manifest>, TarballError> {
+    let mut manifest = None;
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        let display = path.to_string_lossy().to_string();
+
+        let mut components = path.components();
+        let in_root = components.next() == Some(Component::Normal(root.as_ref()))
+            && components.all(|component| matches!(component, Component::Normal(_)));
+        if !in_root {
+            return Err(TarballError::OutsideRoot {
+                path: display,
+                root: root.to_string(),
+            });
+        }
+
+        match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Continuous | EntryType::Directory => {}
+            EntryType::Symlink | EntryType::Link => {
+                let target = entry.link_name()?.unwrap_or_default();
+                // symlinks are relative to their directory, hard links to the archive root
+                let base = match entry.header().entry_type() {
+                    EntryType::Symlink => path.parent().unwrap_or(Path::new("")),
+                    _ => Path::new(""),
+                };
+                if !stays_within(&base.join(target), root) {
+                    return Err(TarballError::LinkEscapesRoot { path: display });
+                }
+            }
+            EntryType::XGlobalHeader => continue,
+            _ => return Err(TarballError::UnsupportedEntry { path: display }),
+        }
+
+        if path == Path::new(root).join("Cargo.toml") {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            manifest = Some(toml::from_str(&contents)?);
+        }
+    }
+
+    Ok(manifest)
+}
+
+/// Whether `path` (relative to the archive root) resolves to a path inside `root`.
+fn stays_within(path: &Path, root: &str) -> bool {
+    let mut resolved = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop();
+            }
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+        if resolved.is_empty() {
+            return false;
+        }
+    }
+    resolved.first() == Some(&root.as_ref())
+}
+
+/// A reader failing once more than `remaining` bytes have been read.
+struct LimitedReader<R> {
+    inner: R,
+    remaining: u64,
+    exceeded: bool,
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        match self.remaining.checked_sub(read as u64) {
+            Some(remaining) => {
+                self.remaining = remaining;
+                Ok(read)
+            }
+            None => {
+                self.exceeded = true;
+                Err(std::io::Error::other("size limit exceeded"))
+            }
+        }
+    }
+}
+
+impl Manifest {
+    /// Builds the index record of the packaged crate version.
+    pub fn into_record(self, checksum: String, yanked: bool) -> CrateVersion {
+        let mut dependencies = Vec::new();
+        let mut add = |deps: BTreeMap<String, Dependency>, kind, target: Option<&String>| {
+            for (name, dep) in deps {
+                dependencies.push(dep.into_record(name, kind, target.cloned()));
+            }
+        };
+        add(self.dependencies, CrateDependencyKind::Normal, None);
+        add(self.dev_dependencies, CrateDependencyKind::Dev, None);
+        add(self.build_dependencies, CrateDependencyKind::Build, None);
+        for (target, deps) in self.target {
+            add(
+                deps.dependencies,
+                CrateDependencyKind::Normal,
+                Some(&target),
+            );
+            add(
+                deps.dev_dependencies,
+                CrateDependencyKind::Dev,
+                Some(&target),
+            );
+            add(
+                deps.build_dependencies,
+                CrateDependencyKind::Build,
+                Some(&target),
+            );
+        }
+
+        let mut record = CrateVersion {
+            name: self.package.name,
+            version: self.package.version,
+            dependencies,
+            checksum,
+            features: HashMap::new(),
+            yanked,
+            links: self.package.links,
+            rust_version: self.package.rust_version,
+            features2: HashMap::new(),
+            v: None,
+        };
+        record.set_features(self.features);
+        record
+    }
+}
+
+impl Dependency {
+    fn into_record(
+        self,
+        name: String,
+        kind: CrateDependencyKind,
+        target: Option<String>,
+    ) -> CrateDependency {
+        let detail = match self {
+            Self::Simple(req) => DependencyDetail {
+                version: Some(req),
+                features: Vec::new(),
+                optional: false,
+                default_features: None,
+                package: None,
+                registry_index: None,
+            },
+            Self::Detailed(detail) => detail,
+        };
+
+        CrateDependency {
+            name,
+            req: detail.version.unwrap_or(VersionReq::STAR),
+            features: detail.features,
+            optional: detail.optional,
+            default_features: detail.default_features.unwrap_or(true),
+            target,
+            kind,
+            registry: detail.registry_index,
+            package: detail.package,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{read_manifest, verify, TarballError, TarballLimits};
+    use flate2::{write::GzEncoder, Compression};
+    use semver::{Version, VersionReq};
+    use tar::EntryType;
+    use valhall_models::crates::CrateDependencyKind;
+
+    /// Packs `files` (path and contents) into a gzipped tarball, like `cargo package` does.
+    pub fn tarball(files: &[(&str, &str)]) -> Vec<u8> {
+        let entries = files
+            .iter()
+            .map(|(path, contents)| (*path, EntryType::Regular, *contents))
+            .collect::<Vec<_>>();
+        tarball_with(&entries)
+    }
+
+    /// Packs entries into a gzipped tarball without sanitizing their paths.
+    ///
+    /// The contents of links are their target. Paths longer than the 100 bytes of
+    /// the header are stored in GNU long name entries, which `tar` sanitizes.
+    fn tarball_with(entries: &[(&str, EntryType, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, kind, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(*kind);
+            header.set_mode(0o644);
+            let data = match kind {
+                EntryType::Symlink | EntryType::Link => {
+                    header.as_gnu_mut().unwrap().linkname[..contents.len()]
+                        .copy_from_slice(contents.as_bytes());
+                    ""
+                }
+                _ => contents,
+            };
+            header.set_size(data.len() as u64);
+            if path.len() <= 100 {
+                header.as_gnu_mut().unwrap().name[..path.len()].copy_from_slice(path.as_bytes());
+                header.set_cksum();
+                builder.append(&header, data.as_bytes()).unwrap();
+            } else {
+                builder
+                    .append_data(&mut header, path, data.as_bytes())
+                    .unwrap();
+            }
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    /// Packs a minimal crate depending on `serde`.
+    pub fn crate_file(name: &str, version: &Version) -> Vec<u8> {
+        let manifest = format!(
+            "[package]\nname = \"{}\"\nversion = \"{}\"\n\n[dependencies]\nserde = \"1\"\n",
+            name, version
+        );
+        tarball(&[(&format!("{}-{}/Cargo.toml", name, version), &manifest)])
+    }
+
+    #[test]
+    fn manifest_into_record() {
+        let manifest = r#"
+            [package]
+            name = "abcd"
+            version = "1.2.0"
+            links = "z"
+            rust-version = "1.70"
+
+            [dependencies]
+            serde = "1.0"
+
+            [dependencies.json]
+            version = "0.4"
+            package = "serde_json"
+            optional = true
+            default-features = false
+            features = ["std"]
+            registry-index = "https://github.com/rust-lang/crates.io-index"
+
+            [dev-dependencies.tempfile]
+            version = "3"
+
+            [target."cfg(windows)".dependencies.winapi]
+            version = "0.3"
+
+            [features]
+            default = ["json"]
+            std = ["json?/std"]
+        "#;
+        let bytes = tarball(&[
+            ("abcd-1.2.0/src/lib.rs", ""),
+            ("abcd-1.2.0/Cargo.toml", manifest),
+        ]);
+
+        let version = Version::new(1, 2, 0);
+        let record = read_manifest(&bytes, "abcd", &version)
+            .unwrap()
+            .into_record("ff".into(), true);
+        assert_eq!(record.name, "abcd");
+        assert_eq!(record.version, version);
+        assert_eq!(record.checksum, "ff");
+        assert!(record.yanked);
+        assert_eq!(record.links.as_deref(), Some("z"));
+        assert_eq!(record.rust_version.as_deref(), Some("1.70"));
+        assert_eq!(record.features["default"], vec!["json"]);
+        assert_eq!(record.features2["std"], vec!["json?/std"]);
+        assert_eq!(record.v, Some(2));
+
+        let deps = record.dependencies;
+        assert_eq!(deps.len(), 4);
+        assert_eq!(deps[0].name, "json");
+        assert_eq!(deps[0].package.as_deref(), Some("serde_json"));
+        assert_eq!(deps[0].req, VersionReq::parse("0.4").unwrap());
+        assert!(deps[0].optional && !deps[0].default_features);
+        assert!(deps[0].registry.is_some());
+        assert_eq!(deps[1].name, "serde");
+        assert!(deps[1].default_features);
+        assert_eq!(deps[2].kind, CrateDependencyKind::Dev);
+        assert_eq!(deps[3].target.as_deref(), Some("cfg(windows)"));
+
+        assert!(read_manifest(&bytes, "abcd", &Version::new(1, 0, 0)).is_err());
+    }
+
+    const MANIFEST: &str = "[package]\nname = \"abcd\"\nversion = \"1.0.0\"\n";
+
+    fn check(entries: &[(&str, EntryType, &str)]) -> Result<(), TarballError> {
+        let version = Version::new(1, 0, 0);
+        verify(
+            &tarball_with(entries),
+            "abcd",
+            &version,
+            &TarballLimits::default(),
+        )
+        .map(|_| ())
+    }
+
+    #[test]
+    fn verify_contents() {
+        use EntryType::{Directory, Regular, Symlink};
+
+        let manifest = ("abcd-1.0.0/Cargo.toml", Regular, MANIFEST);
+        assert!(check(&[
+            ("abcd-1.0.0/", Directory, ""),
+            manifest,
+            ("abcd-1.0.0/src/lib.rs", Regular, ""),
+            ("abcd-1.0.0/src/link.rs", Symlink, "../README.md"),
+        ])
+        .is_ok());
+        // paths over 100 bytes, as in deeply nested test fixtures
+        let long_path = format!("abcd-1.0.0/tests/{}/data.json", "nested/".repeat(20));
+        assert!(check(&[manifest, (&long_path, Regular, "{}")]).is_ok());
+
+        assert!(matches!(
+            check(&[("abcd-1.0.0/src/lib.rs", Regular, "")]),
+            Err(TarballError::MissingManifest(_))
+        ));
+        assert!(matches!(
+            check(&[manifest, ("other-1.0.0/Cargo.toml", Regular, MANIFEST)]),
+            Err(TarballError::OutsideRoot { .. })
+        ));
+        assert!(matches!(
+            check(&[manifest, ("abcd-1.0.0/../../etc/passwd", Regular, "")]),
+            Err(TarballError::OutsideRoot { .. })
+        ));
+        assert!(matches!(
+            check(&[manifest, ("/abcd-1.0.0/lib.rs", Regular, "")]),
+            Err(TarballError::OutsideRoot { .. })
+        ));
+        assert!(matches!(
+            check(&[manifest, ("abcd-1.0.0/src/link", Symlink, "../../../etc")]),
+            Err(TarballError::LinkEscapesRoot { .. })
+        ));
+        assert!(matches!(
+            check(&[manifest, ("abcd-1.0.0/link", Symlink, "/etc/passwd")]),
+            Err(TarballError::LinkEscapesRoot { .. })
+        ));
+        assert!(matches!(
+            check(&[manifest, ("abcd-1.0.0/fifo", EntryType::Fifo, "")]),
+            Err(TarballError::UnsupportedEntry { .. })
+        ));
+        assert!(matches!(
+            check(&[(
+                "abcd-1.0.0/Cargo.toml",
+                Regular,
+                "[package]\nname = \"abcd\"\nversion = \"1.0.1\"\n"
+            )]),
+            Err(TarballError::ManifestMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn verify_limits() {
+        let version = Version::new(1, 0, 0);
+        let padding = "a".repeat(64 * 1024);
+        let bytes = tarball(&[
+            ("abcd-1.0.0/Cargo.toml", MANIFEST),
+            ("abcd-1.0.0/padding", &padding),
+        ]);
+
+        let limits = TarballLimits {
+            max_size: bytes.len() as u64,
+            max_unpacked_size: 80 * 1024,
+        };
+        assert!(verify(&bytes, "abcd", &version, &limits).is_ok());
+
+        let limits = TarballLimits {
+            max_size: bytes.len() as u64 - 1,
+            ..limits
+        };
+        assert!(matches!(
+            verify(&bytes, "abcd", &version, &limits),
+            Err(TarballError::TooLarge { .. })
+        ));
+
+        // gzip compresses the padding to almost nothing
+        let limits = TarballLimits {
+            max_size: bytes.len() as u64,
+            max_unpacked_size: 32 * 1024,
+        };
+        assert!(matches!(
+            verify(&bytes, "abcd", &version, &limits),
+            Err(TarballError::UnpackedTooLarge { limit: 32768 })
+        ));
+    }
+}